@@ -40,7 +40,15 @@ enum SubCommand {
         task: Vec<sign::TaskType>,
     },
     #[clap(about = "Rename airport subscriptions")]
-    Rename,
+    Rename {
+        #[clap(
+            long,
+            about = "Print node changes since last run without writing output"
+        )]
+        diff: bool,
+    },
+    #[clap(about = "Serve renamed subscriptions over HTTP")]
+    Serve,
 }
 
 #[tokio::main]
@@ -81,8 +89,11 @@ async fn main() -> Result<()> {
                 task.run(&config.sign, &notifier).await;
             }
         }
-        SubCommand::Rename => {
-            renamer::main(notifier, config.renamer).await?;
+        SubCommand::Rename { diff } => {
+            renamer::main(notifier, config.renamer, diff).await?;
+        }
+        SubCommand::Serve => {
+            renamer::serve(config.renamer).await?;
         }
     }
 
@@ -0,0 +1,635 @@
+//! Clash 配置文件的解析和生成
+use super::{
+    config::ClashOutput,
+    output,
+    parse::{atob, decode_component, encode_component, json_str, Airport, Node, Uri},
+    view::View,
+};
+use anyhow::{anyhow, bail, Result};
+use serde_yaml::{Mapping, Value};
+use std::collections::HashMap;
+
+/// 生成的主分组名，模板中的规则应当指向这个分组
+const PROXY: &str = "PROXY";
+const AUTO: &str = "自动选择";
+const FALLBACK: &str = "故障转移";
+
+#[derive(Debug, Deserialize)]
+struct Document {
+    proxies: Vec<Value>,
+}
+
+/// 内容是否是 clash 配置（有顶层 `proxies` 字段）
+pub fn is_clash(content: &str) -> bool {
+    content
+        .lines()
+        .any(|line| line.trim_end() == "proxies:" || line.starts_with("proxies: "))
+}
+
+/// 把 clash 配置中的 `proxies` 转换为节点，不支持的节点会被跳过
+pub fn parse(content: &str) -> Result<Vec<Node>> {
+    let document: Document = serde_yaml::from_str(content)?;
+    let mut nodes = vec![];
+    for proxy in document.proxies {
+        match View(&proxy).to_node() {
+            Ok(node) => nodes.push(node),
+            Err(e) => warn!("error parse clash proxy: {:?}. ignore.", e),
+        }
+    }
+    Ok(nodes)
+}
+
+/// clash proxy 的只读视图
+pub(super) type Proxy<'a> = View<'a, Value>;
+impl<'a> Proxy<'a> {
+    fn to_node(&self) -> Result<Node> {
+        let kind = self.require("type")?;
+        match kind {
+            "vmess" => self.vmess(),
+            "ss" => self.shadowsocks(),
+            "ssr" => self.ssr(),
+            "trojan" => self.trojan(),
+            _ => Err(anyhow!("clash: unsupported proxy type {}", kind)),
+        }
+    }
+
+    fn vmess(&self) -> Result<Node> {
+        let network = self.str("network").unwrap_or("tcp");
+        let (host, path) = match network {
+            "ws" => {
+                let opts = self.opts("ws-opts");
+                (
+                    opts.opts("headers").str("Host"),
+                    opts.str("path").or_else(|| self.str("ws-path")),
+                )
+            }
+            "h2" => {
+                let opts = self.opts("h2-opts");
+                let host = opts
+                    .get("host")
+                    .and_then(Value::as_sequence)
+                    .and_then(|hosts| hosts.first())
+                    .and_then(Value::as_str);
+                (host, opts.str("path"))
+            }
+            "grpc" => (None, self.opts("grpc-opts").str("grpc-service-name")),
+            _ => (None, None),
+        };
+        let alter_id = match self.get("alterId") {
+            Some(Value::Number(n)) => n.as_u64().unwrap_or(0),
+            Some(Value::String(s)) => s.parse().unwrap_or(0),
+            _ => 0,
+        };
+        let inner = serde_json::json!({
+            "v": "2",
+            "ps": self.require("name")?,
+            "add": self.require("server")?,
+            "port": self.port("port")?.to_string(),
+            "id": self.require("uuid")?,
+            "aid": alter_id.to_string(),
+            "scy": self.str("cipher").unwrap_or("auto"),
+            "net": network,
+            "type": "none",
+            "host": host.unwrap_or_default(),
+            "path": path.unwrap_or_default(),
+            "tls": if self.bool("tls") { "tls" } else { "" },
+            "sni": self.str("servername").unwrap_or_default(),
+        });
+        Ok(Node::VMess { inner })
+    }
+
+    fn shadowsocks(&self) -> Result<Node> {
+        let opts = self.opts("plugin-opts");
+        let plugin = match self.str("plugin") {
+            Some("obfs") => {
+                let mut plugin = format!("obfs-local;obfs={}", opts.str("mode").unwrap_or("http"));
+                if let Some(host) = opts.str("host") {
+                    plugin += &format!(";obfs-host={}", host);
+                }
+                Some(plugin)
+            }
+            Some("v2ray-plugin") => {
+                let mut plugin = "v2ray-plugin".to_string();
+                if opts.bool("tls") {
+                    plugin += ";tls";
+                }
+                if let Some(host) = opts.str("host") {
+                    plugin += &format!(";host={}", host);
+                }
+                if let Some(path) = opts.str("path") {
+                    plugin += &format!(";path={}", path);
+                }
+                Some(plugin)
+            }
+            Some(other) => return Err(anyhow!("clash: unsupported ss plugin {}", other)),
+            None => None,
+        };
+        Ok(Node::Shadowsocks {
+            method: self.require("cipher")?.to_string(),
+            password: self.require("password")?.to_string(),
+            server: self.require("server")?.to_string(),
+            port: self.port("port")?,
+            plugin,
+            query: vec![],
+            tag: self.require("name")?.to_string(),
+        })
+    }
+
+    fn ssr(&self) -> Result<Node> {
+        let b64 = |s: &str| base64::encode_config(s, base64::URL_SAFE_NO_PAD);
+        let path = format!(
+            "{}:{}:{}:{}:{}:{}/",
+            self.require("server")?,
+            self.port("port")?,
+            self.require("protocol")?,
+            self.require("cipher")?,
+            self.require("obfs")?,
+            b64(self.require("password")?)
+        );
+        let mut query = HashMap::new();
+        query.insert(
+            "obfsparam".to_string(),
+            b64(self.str("obfs-param").unwrap_or_default()),
+        );
+        query.insert(
+            "protoparam".to_string(),
+            b64(self.str("protocol-param").unwrap_or_default()),
+        );
+        query.insert("remarks".to_string(), b64(self.require("name")?));
+        Ok(Node::Ssr { path, query })
+    }
+
+    fn trojan(&self) -> Result<Node> {
+        let mut uri = Uri::new(
+            "trojan",
+            encode_component(self.require("password")?),
+            self.require("server")?,
+            self.port("port")?,
+            self.require("name")?,
+        );
+        if let Some(sni) = self.str("sni") {
+            uri.push_param("sni", sni);
+        }
+        if self.bool("skip-cert-verify") {
+            uri.push_param("allowInsecure", "1");
+        }
+        match self.str("network") {
+            Some("ws") => {
+                let opts = self.opts("ws-opts");
+                uri.push_param("type", "ws");
+                if let Some(host) = opts.opts("headers").str("Host") {
+                    uri.push_param("host", host);
+                }
+                if let Some(path) = opts.str("path") {
+                    uri.push_param("path", path);
+                }
+            }
+            Some("grpc") => {
+                uri.push_param("type", "grpc");
+                if let Some(name) = self.opts("grpc-opts").str("grpc-service-name") {
+                    uri.push_param("serviceName", name);
+                }
+            }
+            _ => {}
+        }
+        Ok(Node::Trojan { inner: uri })
+    }
+}
+
+/// 生成完整的 clash 配置。
+///
+/// `template` 是规则模板的内容，其中的 `proxies` 会被替换，`proxy-groups` 会追加在生成的分组之后。
+/// 无法转换的节点记录在 `skipped` 中。
+pub fn render(
+    airports: &[Airport],
+    template: Option<&str>,
+    options: &ClashOutput,
+    skipped: &mut Vec<String>,
+) -> Result<String> {
+    let mut proxies = vec![];
+    let mut all = vec![];
+    let mut airport_groups = vec![];
+    let mut region_groups: Vec<(String, Vec<String>)> = vec![];
+
+    for airport in airports {
+        let mut names = vec![];
+        for (i, node) in airport.nodes.iter().enumerate() {
+            let proxy = match to_proxy(node) {
+                Ok(proxy) => proxy,
+                Err(e) => {
+                    output::skip(skipped, node, &e);
+                    continue;
+                }
+            };
+            let name = node.name()?;
+            if let Some(region) = airport.region(i) {
+                let region = region.to_string();
+                match region_groups.iter_mut().find(|(r, _)| *r == region) {
+                    Some((_, names)) => names.push(name.clone()),
+                    None => region_groups.push((region, vec![name.clone()])),
+                }
+            }
+            names.push(name.clone());
+            all.push(name);
+            proxies.push(Value::Mapping(proxy));
+        }
+        if !names.is_empty() {
+            airport_groups.push((airport.name.clone(), names));
+        }
+    }
+    if all.is_empty() {
+        all.push("DIRECT".to_string());
+    }
+
+    let mut groups = vec![];
+    let mut main = vec![AUTO.to_string(), FALLBACK.to_string()];
+    main.extend(airport_groups.iter().map(|(name, _)| name.clone()));
+    main.extend(region_groups.iter().map(|(name, _)| name.clone()));
+    main.push("DIRECT".to_string());
+    groups.push(group(PROXY, "select", main, None));
+    groups.push(group(AUTO, "url-test", all.clone(), Some(options)));
+    groups.push(group(FALLBACK, "fallback", all, Some(options)));
+    for (name, names) in airport_groups {
+        groups.push(group(&name, "select", names, None));
+    }
+    for (name, names) in region_groups {
+        groups.push(group(&name, "url-test", names, Some(options)));
+    }
+
+    let mut document: Mapping = match template {
+        Some(template) => serde_yaml::from_str(template)?,
+        None => serde_yaml::from_str(DEFAULT_TEMPLATE)?,
+    };
+    if let Some(Value::Sequence(extra)) = document.remove(&"proxy-groups".into()) {
+        groups.extend(extra);
+    }
+    document.insert("proxies".into(), Value::Sequence(proxies));
+    document.insert("proxy-groups".into(), Value::Sequence(groups));
+    if !document.contains_key(&"rules".into()) {
+        document.insert(
+            "rules".into(),
+            Value::Sequence(vec![format!("MATCH,{}", PROXY).into()]),
+        );
+    }
+
+    Ok(serde_yaml::to_string(&document)?)
+}
+
+const DEFAULT_TEMPLATE: &str = r#"
+mixed-port: 7890
+allow-lan: false
+mode: rule
+log-level: info
+"#;
+
+fn group(name: &str, kind: &str, proxies: Vec<String>, test: Option<&ClashOutput>) -> Value {
+    let mut group = Mapping::new();
+    group.insert("name".into(), name.into());
+    group.insert("type".into(), kind.into());
+    group.insert(
+        "proxies".into(),
+        Value::Sequence(proxies.into_iter().map(Value::from).collect()),
+    );
+    if let Some(options) = test {
+        group.insert("url".into(), options.test_url.as_str().into());
+        group.insert("interval".into(), options.interval.into());
+    }
+    Value::Mapping(group)
+}
+
+/// 按顺序构造 clash 的 proxy
+struct Builder(Mapping);
+impl Builder {
+    fn new(name: String, kind: &str, server: &str, port: u16) -> Self {
+        let mut builder = Self(Mapping::new());
+        builder.set("name", name);
+        builder.set("type", kind);
+        builder.set("server", server);
+        builder.set("port", port);
+        builder
+    }
+
+    fn set(&mut self, key: &str, value: impl Into<Value>) {
+        self.0.insert(key.into(), value.into());
+    }
+
+    fn set_opt(&mut self, key: &str, value: Option<String>) {
+        if let Some(value) = value.filter(|v| !v.is_empty()) {
+            self.set(key, value);
+        }
+    }
+
+    /// `h2,http/1.1` 形式的 alpn
+    fn set_alpn(&mut self, alpn: Option<String>) {
+        if let Some(alpn) = alpn.filter(|v| !v.is_empty()) {
+            let alpn: Vec<Value> = alpn.split(',').map(Value::from).collect();
+            self.set("alpn", alpn);
+        }
+    }
+
+    /// ws / grpc / h2 传输层
+    fn set_transport(&mut self, network: &str, host: Option<String>, path: Option<String>) {
+        match network {
+            "ws" => {
+                self.set("network", "ws");
+                let mut opts = Mapping::new();
+                if let Some(path) = path.filter(|v| !v.is_empty()) {
+                    opts.insert("path".into(), path.into());
+                }
+                if let Some(host) = host.filter(|v| !v.is_empty()) {
+                    let mut headers = Mapping::new();
+                    headers.insert("Host".into(), host.into());
+                    opts.insert("headers".into(), Value::Mapping(headers));
+                }
+                self.set("ws-opts", opts);
+            }
+            "h2" | "http" => {
+                self.set("network", "h2");
+                let mut opts = Mapping::new();
+                if let Some(host) = host.filter(|v| !v.is_empty()) {
+                    opts.insert("host".into(), Value::Sequence(vec![host.into()]));
+                }
+                if let Some(path) = path.filter(|v| !v.is_empty()) {
+                    opts.insert("path".into(), path.into());
+                }
+                self.set("h2-opts", opts);
+            }
+            "grpc" => {
+                self.set("network", "grpc");
+                let mut opts = Mapping::new();
+                if let Some(name) = path.filter(|v| !v.is_empty()) {
+                    opts.insert("grpc-service-name".into(), name.into());
+                }
+                self.set("grpc-opts", opts);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// 节点转换为 clash 的 proxy
+pub fn to_proxy(node: &Node) -> Result<Mapping> {
+    let name = node.name()?;
+    let builder = match node {
+        Node::VMess { inner } => {
+            let server = json_str(inner, "add").ok_or_else(|| anyhow!("vmess: add not found"))?;
+            let port = json_str(inner, "port")
+                .ok_or_else(|| anyhow!("vmess: port not found"))?
+                .parse()?;
+            let mut b = Builder::new(name, "vmess", &server, port);
+            b.set_opt("uuid", json_str(inner, "id"));
+            let aid: u32 = json_str(inner, "aid")
+                .and_then(|aid| aid.parse().ok())
+                .unwrap_or(0);
+            b.set("alterId", aid);
+            b.set(
+                "cipher",
+                json_str(inner, "scy").unwrap_or_else(|| "auto".to_string()),
+            );
+            if json_str(inner, "tls").as_deref() == Some("tls") {
+                b.set("tls", true);
+                b.set_opt("servername", json_str(inner, "sni"));
+            }
+            let network = json_str(inner, "net").unwrap_or_default();
+            b.set_transport(&network, json_str(inner, "host"), json_str(inner, "path"));
+            b
+        }
+        Node::Ssr { path, query } => {
+            let parts: Vec<&str> = path.trim_end_matches('/').rsplitn(6, ':').collect();
+            if parts.len() != 6 {
+                bail!("ssr: invalid path {:?}", path);
+            }
+            let param = |key: &str| {
+                query
+                    .get(key)
+                    .and_then(|v| atob(v, base64::URL_SAFE_NO_PAD).ok())
+            };
+            let mut b = Builder::new(name, "ssr", parts[5], parts[4].parse()?);
+            b.set("cipher", parts[2]);
+            b.set("password", atob(parts[0], base64::URL_SAFE_NO_PAD)?);
+            b.set("protocol", parts[3]);
+            b.set("obfs", parts[1]);
+            b.set_opt("protocol-param", param("protoparam"));
+            b.set_opt("obfs-param", param("obfsparam"));
+            b
+        }
+        Node::Shadowsocks {
+            method,
+            password,
+            server,
+            port,
+            plugin,
+            ..
+        } => {
+            let mut b = Builder::new(name, "ss", server, *port);
+            b.set("cipher", method.as_str());
+            b.set("password", password.as_str());
+            if let Some(plugin) = plugin {
+                let mut parts = plugin.split(';');
+                let plugin_name = parts.next().unwrap_or_default();
+                let opts: Vec<(&str, &str)> = parts
+                    .map(|part| part.split_once('=').unwrap_or((part, "")))
+                    .collect();
+                let opt = |key: &str| opts.iter().find(|(k, _)| *k == key).map(|(_, v)| *v);
+                let mut plugin_opts = Mapping::new();
+                match plugin_name {
+                    "obfs-local" | "simple-obfs" => {
+                        b.set("plugin", "obfs");
+                        plugin_opts.insert("mode".into(), opt("obfs").unwrap_or("http").into());
+                        if let Some(host) = opt("obfs-host") {
+                            plugin_opts.insert("host".into(), host.into());
+                        }
+                    }
+                    "v2ray-plugin" => {
+                        b.set("plugin", "v2ray-plugin");
+                        plugin_opts.insert("mode".into(), "websocket".into());
+                        if opt("tls").is_some() {
+                            plugin_opts.insert("tls".into(), true.into());
+                        }
+                        if let Some(host) = opt("host") {
+                            plugin_opts.insert("host".into(), host.into());
+                        }
+                        if let Some(path) = opt("path") {
+                            plugin_opts.insert("path".into(), path.into());
+                        }
+                    }
+                    other => bail!("ss: plugin {} is not supported by clash", other),
+                }
+                b.set("plugin-opts", plugin_opts);
+            }
+            b
+        }
+        Node::Trojan { inner } => {
+            let mut b = Builder::new(name, "trojan", &inner.server, inner.port);
+            b.set("password", decode_component(&inner.userinfo)?);
+            b.set_opt("sni", inner.param("sni").or_else(|| inner.param("peer")));
+            set_uri_common(&mut b, inner);
+            b
+        }
+        Node::Vless { inner } => {
+            let mut b = Builder::new(name, "vless", &inner.server, inner.port);
+            b.set("uuid", decode_component(&inner.userinfo)?);
+            b.set_opt("flow", inner.param("flow"));
+            match inner.param("security").as_deref() {
+                Some("tls") => b.set("tls", true),
+                Some("reality") => {
+                    b.set("tls", true);
+                    let mut opts = Mapping::new();
+                    if let Some(pbk) = inner.param("pbk") {
+                        opts.insert("public-key".into(), pbk.into());
+                    }
+                    if let Some(sid) = inner.param("sid") {
+                        opts.insert("short-id".into(), sid.into());
+                    }
+                    b.set("reality-opts", opts);
+                }
+                _ => {}
+            }
+            b.set_opt("servername", inner.param("sni"));
+            b.set_opt("client-fingerprint", inner.param("fp"));
+            set_uri_common(&mut b, inner);
+            b
+        }
+        Node::Hysteria2 { inner } => {
+            let mut b = Builder::new(name, "hysteria2", &inner.server, inner.port);
+            b.set_opt("password", Some(decode_component(&inner.userinfo)?));
+            b.set_opt("obfs", inner.param("obfs"));
+            b.set_opt("obfs-password", inner.param("obfs-password"));
+            b.set_opt("sni", inner.param("sni"));
+            set_uri_common(&mut b, inner);
+            b
+        }
+        Node::Tuic { inner } => {
+            let userinfo = decode_component(&inner.userinfo)?;
+            let (uuid, password) = userinfo.split_once(':').unwrap_or((&userinfo, ""));
+            let mut b = Builder::new(name, "tuic", &inner.server, inner.port);
+            b.set("uuid", uuid);
+            b.set_opt("password", Some(password.to_string()));
+            b.set_opt("congestion-controller", inner.param("congestion_control"));
+            b.set_opt("udp-relay-mode", inner.param("udp_relay_mode"));
+            b.set_opt("sni", inner.param("sni"));
+            set_uri_common(&mut b, inner);
+            b
+        }
+        Node::Opaque { .. } => bail!("opaque node cannot be converted"),
+    };
+    Ok(builder.0)
+}
+
+/// 链接类节点共有的 alpn / 证书校验 / 传输层参数
+fn set_uri_common(b: &mut Builder, uri: &Uri) {
+    b.set_alpn(uri.param("alpn"));
+    if uri.insecure() {
+        b.set("skip-cert-verify", true);
+    }
+    if let Some(network) = uri.param("type") {
+        let path = match network.as_str() {
+            "grpc" => uri.param("serviceName"),
+            _ => uri.param("path"),
+        };
+        b.set_transport(&network, uri.param("host"), path);
+    }
+}
+
+#[test]
+fn test_parse() {
+    let s = r#"
+port: 7890
+proxies:
+  - name: "香港 01"
+    type: vmess
+    server: hk.example.com
+    port: 443
+    uuid: b831381d-6324-4d53-ad4f-8cda48b30811
+    alterId: 0
+    cipher: auto
+    tls: true
+    network: ws
+    ws-opts:
+      path: /ray
+      headers:
+        Host: cdn.example.com
+  - { name: "日本 ss", type: ss, server: 1.2.3.4, port: 8388, cipher: aes-256-gcm, password: "p@ss", plugin: obfs, plugin-opts: { mode: tls, host: bing.com } }
+  - { name: ssr, type: ssr, server: 1.2.3.4, port: 8389, cipher: aes-256-cfb, password: pass, obfs: plain, protocol: origin }
+  - { name: "美国 trojan", type: trojan, server: us.example.com, port: 443, password: "pass word", sni: us.example.com, skip-cert-verify: true }
+  - { name: wg, type: wireguard, server: 1.2.3.4, port: 51820 }
+  - { name: big, type: ss, server: 1.2.3.4, port: 70000, cipher: aes-256-gcm, password: p }
+rules:
+  - MATCH,DIRECT
+"#;
+    assert!(is_clash(s));
+    assert!(!is_clash("dm1lc3M6Ly8="));
+
+    let nodes = parse(s).unwrap();
+    assert_eq!(nodes.len(), 4);
+    let names: Vec<String> = nodes.iter().map(|n| n.name().unwrap()).collect();
+    assert_eq!(names, vec!["香港 01", "日本 ss", "ssr", "美国 trojan"]);
+
+    match &nodes[0] {
+        Node::VMess { inner } => {
+            assert_eq!(inner["add"], "hk.example.com");
+            assert_eq!(inner["host"], "cdn.example.com");
+            assert_eq!(inner["path"], "/ray");
+            assert_eq!(inner["tls"], "tls");
+        }
+        _ => panic!("not a vmess node"),
+    }
+    assert_eq!(
+        nodes[1].to_string(),
+        "ss://YWVzLTI1Ni1nY206cEBzcw@1.2.3.4:8388/?plugin=obfs-local%3Bobfs%3Dtls%3Bobfs-host%3Dbing.com#%E6%97%A5%E6%9C%AC%20ss"
+    );
+    assert_eq!(
+        nodes[3].to_string(),
+        "trojan://pass%20word@us.example.com:443?sni=us.example.com&allowInsecure=1#%E7%BE%8E%E5%9B%BD%20trojan"
+    );
+}
+
+#[test]
+fn test_render() {
+    use super::{config::subscription, region::Regions};
+
+    let links = [
+        "vless://b831381d-6324-4d53-ad4f-8cda48b30811@1.2.3.4:443?encryption=none&flow=xtls-rprx-vision&security=reality&sni=www.microsoft.com&fp=chrome&pbk=pbk&sid=6ba85179&type=tcp#%E9%A6%99%E6%B8%AF%2001",
+        "trojan://pass@jp.example.com:443?sni=jp.example.com&type=ws&path=%2Fws#%E6%97%A5%E6%9C%AC%2001",
+        "wireguard://unknown@1.2.3.4:51820#wg",
+    ];
+    // 机场名中的地区不应影响节点的分组
+    let mut airport = Airport::new(
+        "HK Cloud",
+        links.join("\n"),
+        super::config::Format::Base64,
+        true,
+    )
+    .unwrap();
+    airport
+        .rename(&subscription(""), &Regions::default())
+        .unwrap();
+    let options: ClashOutput = toml::from_str("").unwrap();
+    let template = "rules:\n  - DOMAIN-SUFFIX,cn,DIRECT\n  - MATCH,PROXY\n";
+    let mut skipped = vec![];
+    let rendered = render(&[airport], Some(template), &options, &mut skipped).unwrap();
+    assert_eq!(skipped.len(), 1);
+    let document: Value = serde_yaml::from_str(&rendered).unwrap();
+
+    let proxies = document["proxies"].as_sequence().unwrap();
+    assert_eq!(proxies.len(), 2);
+    assert_eq!(proxies[0]["type"], Value::from("vless"));
+    assert_eq!(proxies[0]["reality-opts"]["public-key"], Value::from("pbk"));
+    assert_eq!(proxies[1]["ws-opts"]["path"], Value::from("/ws"));
+
+    let groups = document["proxy-groups"].as_sequence().unwrap();
+    let names: Vec<&str> = groups.iter().map(|g| g["name"].as_str().unwrap()).collect();
+    assert_eq!(
+        names,
+        vec![PROXY, AUTO, FALLBACK, "HK Cloud", "🇭🇰 HK", "🇯🇵 JP"]
+    );
+    assert_eq!(
+        groups[4]["proxies"],
+        Value::from(vec!["香港 01 - HK Cloud"])
+    );
+    assert_eq!(
+        groups[5]["proxies"],
+        Value::from(vec!["日本 01 - HK Cloud"])
+    );
+    assert_eq!(document["rules"].as_sequence().unwrap().len(), 2);
+}
@@ -1,13 +1,262 @@
+use super::{
+    filter::Filter,
+    region::Region,
+    userinfo::{self, UserInfo},
+};
 use anyhow::{bail, Result};
 use chrono::{DateTime, Utc};
-use std::{path::PathBuf, time::SystemTime};
-use tokio::fs;
+use futures_util::future;
+use request::header;
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, SystemTime},
+};
+use tokio::{fs, time};
 
 #[derive(Debug, Deserialize)]
 pub struct Config {
-    pub output: PathBuf,
+    /// 单个 base64 输出，等价于 `outputs` 中一个 `format = "base64"` 的输出
+    #[serde(default)]
+    pub output: Option<PathBuf>,
+    #[serde(default)]
+    pub outputs: Vec<Output>,
+    #[serde(deserialize_with = "unique_subscriptions")]
     pub subscriptions: Vec<Subscription>,
     pub receiver: String,
+    /// 自定义地区，优先于内置的地区表
+    #[serde(default)]
+    pub regions: Vec<Region>,
+    /// 对所有订阅生效，只保留匹配的节点
+    #[serde(default)]
+    pub include: Filter,
+    /// 对所有订阅生效，去掉匹配的节点。默认去掉名字里带 `剩余`、`流量`、`官网` 等的节点，
+    /// 配置后覆盖默认值
+    #[serde(default = "Filter::junk")]
+    pub exclude: Filter,
+    /// 流量和到期提醒
+    #[serde(default)]
+    pub warning: Warning,
+    /// 同时下载的订阅数
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+    /// 去掉不同机场之间重复的节点（同一机场内的重复节点保留），不配置时不去重
+    #[serde(default)]
+    pub dedup: Option<Dedup>,
+    /// 探测节点是否可达，不配置时不探测
+    #[serde(default)]
+    pub probe: Option<Probe>,
+    /// `serve` 子命令的配置
+    #[serde(default)]
+    pub serve: Option<Serve>,
+}
+impl Config {
+    /// 所有的输出，包括旧式的 `output`
+    pub fn outputs(&self) -> Vec<Output> {
+        let legacy = self.output.iter().map(|path| Output {
+            name: "default".to_string(),
+            path: path.clone(),
+            format: OutputFormat::Base64,
+            subscriptions: vec![],
+            include: Filter::default(),
+            exclude: Filter::default(),
+            regions: vec![],
+        });
+        legacy.chain(self.outputs.iter().cloned()).collect()
+    }
+}
+
+/// 输出、去重和通知中都用名字区分机场，不允许重名
+fn unique_subscriptions<'de, D>(deserializer: D) -> Result<Vec<Subscription>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::de::Deserialize;
+    let subscriptions = Vec::<Subscription>::deserialize(deserializer)?;
+    let mut names = HashSet::new();
+    for sub in &subscriptions {
+        if !names.insert(sub.name.as_str()) {
+            return Err(serde::de::Error::custom(format!(
+                "duplicate subscription name {:?}",
+                sub.name
+            )));
+        }
+    }
+    Ok(subscriptions)
+}
+
+fn default_concurrency() -> usize {
+    4
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Warning {
+    /// 已用流量超过总流量的这个比例时提醒
+    #[serde(default = "default_usage")]
+    pub usage: f64,
+    /// 距离到期不足这么多天时提醒
+    #[serde(default = "default_expire_days")]
+    pub expire_days: i64,
+}
+impl Default for Warning {
+    fn default() -> Self {
+        Self {
+            usage: default_usage(),
+            expire_days: default_expire_days(),
+        }
+    }
+}
+
+fn default_usage() -> f64 {
+    0.9
+}
+
+fn default_expire_days() -> i64 {
+    7
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Probe {
+    /// 单个节点的超时时间，单位毫秒
+    #[serde(default = "default_probe_timeout")]
+    pub timeout: u64,
+    /// 同时探测的节点数
+    #[serde(default = "default_probe_concurrency")]
+    pub concurrency: usize,
+    /// 对使用 TLS 的节点再做一次 TLS 握手
+    #[serde(default)]
+    pub tls: bool,
+    /// 如何处理不可达的节点
+    #[serde(default)]
+    pub action: ProbeAction,
+    /// `action = "tag"` 时加在节点名前面的标记
+    #[serde(default = "default_probe_tag")]
+    pub tag: String,
+    /// 每个机场内按延迟从低到高排序
+    #[serde(default)]
+    pub sort: bool,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProbeAction {
+    /// 去掉不可达的节点
+    #[default]
+    Drop,
+    /// 保留不可达的节点，在名字前加上标记
+    Tag,
+}
+
+fn default_probe_timeout() -> u64 {
+    3000
+}
+
+fn default_probe_concurrency() -> usize {
+    32
+}
+
+fn default_probe_tag() -> String {
+    "❌".to_string()
+}
+
+/// 通过 HTTP 提供输出文件
+#[derive(Debug, Deserialize)]
+pub struct Serve {
+    /// 监听地址，如 `0.0.0.0:8080`
+    pub listen: String,
+    /// 输出文件超过这么多秒后，下次请求时重新生成
+    #[serde(default = "default_ttl")]
+    pub ttl: u64,
+    pub users: Vec<User>,
+}
+
+/// 订阅地址为 `/{token}/{输出名}`
+#[derive(Debug, Deserialize)]
+pub struct User {
+    pub name: String,
+    pub token: String,
+    /// 允许访问的输出，为空时可以访问全部
+    #[serde(default)]
+    pub outputs: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct Dedup {
+    /// 重复时优先保留这些机场的节点，按顺序；其余的保留订阅顺序中的第一个
+    #[serde(default)]
+    pub prefer: Vec<String>,
+}
+
+/// 一个输出文件
+#[derive(Debug, Deserialize, Clone)]
+pub struct Output {
+    pub name: String,
+    pub path: PathBuf,
+    #[serde(flatten)]
+    pub format: OutputFormat,
+    /// 只输出这些订阅的节点，为空时输出全部
+    #[serde(default)]
+    pub subscriptions: Vec<String>,
+    /// 只保留匹配的节点，名字按重命名后的算；原样保留的节点不参与过滤
+    #[serde(default)]
+    pub include: Filter,
+    /// 去掉匹配的节点
+    #[serde(default)]
+    pub exclude: Filter,
+    /// 只保留这些地区（如 `HK`）的节点，为空时不限制
+    #[serde(default)]
+    pub regions: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(tag = "format", rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// base64 编码的链接列表
+    Base64,
+    /// 明文链接列表，一行一个
+    Plain,
+    Clash(ClashOutput),
+    SingBox(SingBoxOutput),
+    /// Surge 的 `[Proxy]` 节点行
+    Surge,
+    /// Quantumult X 的 `[server_local]` 节点行
+    QuantumultX,
+    /// Loon 的 `[Proxy]` 节点行
+    Loon,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ClashOutput {
+    /// 规则模板，生成的 `proxies` 和 `proxy-groups` 会合并进去
+    #[serde(default)]
+    pub template: Option<PathBuf>,
+    /// url-test / fallback 分组的测速链接
+    #[serde(default = "default_test_url")]
+    pub test_url: String,
+    /// 测速间隔，单位秒
+    #[serde(default = "default_interval")]
+    pub interval: u32,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct SingBoxOutput {
+    /// 基础配置，生成的节点和分组会插入到其中 `outbounds` 的最前面
+    pub template: PathBuf,
+    /// urltest 分组的测速链接
+    #[serde(default = "default_test_url")]
+    pub test_url: String,
+    /// 测速间隔，单位秒
+    #[serde(default = "default_interval")]
+    pub interval: u32,
+}
+
+fn default_test_url() -> String {
+    "http://www.gstatic.com/generate_204".to_string()
+}
+
+fn default_interval() -> u32 {
+    300
 }
 
 #[derive(Debug, Deserialize)]
@@ -15,41 +264,736 @@ pub struct Subscription {
     pub name: String,
     pub cache: PathBuf,
     pub url: String,
+    /// 备用地址，主地址失败时按顺序尝试
+    #[serde(default)]
+    pub mirrors: Vec<String>,
+    /// 同时请求主地址和所有备用地址，使用最先成功的
+    #[serde(default)]
+    pub race: bool,
     #[serde(default)]
     pub replacements: Vec<String>,
+    /// 手动配置的到期时间，不配置时使用响应头中的 `expire`
     #[serde(default)]
     pub expire: Option<DateTime<chrono::FixedOffset>>,
+    /// 无法识别的节点原样保留，而不是丢弃
+    #[serde(default)]
+    pub passthrough: bool,
+    /// 订阅内容的格式，默认自动识别
+    #[serde(default)]
+    pub format: Format,
+    /// 按地区统一节点名，如 `🇭🇰 HK 01 - 机场`
+    #[serde(default)]
+    pub normalize: bool,
+    /// 节点名模板，可用 `{name}` `{airport}` `{index}` `{region}` `{protocol}` `{multiplier}`
+    #[serde(default)]
+    pub name_template: Option<String>,
+    /// 只保留匹配的节点，在全局的过滤之后生效
+    #[serde(default)]
+    pub include: Filter,
+    /// 去掉匹配的节点，在全局的过滤之后生效
+    #[serde(default)]
+    pub exclude: Filter,
+    /// 下载或解析失败时，使用不超过这么多小时之前的缓存，为 0 时不使用
+    #[serde(default = "default_stale_hours")]
+    pub stale_hours: u64,
+    /// 缓存的有效期，单位秒，过期后重新请求
+    #[serde(default = "default_ttl")]
+    pub ttl: u64,
+    /// 单次下载的超时时间，单位秒
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+    /// 下载失败后的重试次数，每次重试的间隔翻倍
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+    /// 下载订阅用的代理，如 `socks5://127.0.0.1:1080`
+    #[serde(default)]
+    pub proxy: Option<String>,
+    /// 很多机场按 User-Agent 返回不同格式的订阅，如 `clash-verge/v1.3.8`
+    #[serde(default)]
+    pub user_agent: Option<String>,
+    /// 额外的请求头
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
 }
+
+fn default_timeout() -> u64 {
+    30
+}
+
+fn default_retries() -> u32 {
+    2
+}
+
+fn default_ttl() -> u64 {
+    3600
+}
+
+fn default_stale_hours() -> u64 {
+    72
+}
+
+/// 订阅内容的格式
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Format {
+    #[default]
+    Auto,
+    /// 链接列表，明文或 base64 编码
+    Base64,
+    Clash,
+    SingBox,
+    Sip008,
+}
+/// 订阅没有配置 `user_agent` 时使用的 User-Agent
+const USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// 下载订阅的客户端。下载的总时间由订阅的 `timeout` 限制，这里只限制连接的时间
+pub fn client_builder() -> request::ClientBuilder {
+    request::Client::builder()
+        .user_agent(USER_AGENT)
+        .connect_timeout(Duration::from_secs(10))
+}
+
+/// 订阅的内容和响应头中的流量信息
+#[derive(Debug)]
+pub struct Fetched {
+    pub content: String,
+    pub userinfo: Option<UserInfo>,
+    /// 主地址失败、从备用地址下载成功时，备用地址的序号（从 1 开始）
+    pub mirror: Option<usize>,
+    meta: Meta,
+    source: Source,
+}
+
+/// 订阅内容的来源，决定 `save` 时要写入什么
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Source {
+    /// 缓存未过期，直接读取
+    Cache,
+    /// 重新下载
+    Download,
+    /// 服务器返回 304，内容沿用缓存
+    NotModified,
+}
+
+/// 和缓存文件放在一起的响应头信息，如 `cache.txt.meta.json`
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct Meta {
+    #[serde(default)]
+    userinfo: Option<String>,
+    #[serde(default)]
+    etag: Option<String>,
+    #[serde(default)]
+    last_modified: Option<String>,
+    /// 上次向服务器确认内容的时间戳，304 时只更新这里而不重写缓存
+    #[serde(default)]
+    checked_at: Option<i64>,
+}
+
 impl Subscription {
-    pub async fn get(&self) -> Result<String> {
-        if let Some(t) = self.expire.as_ref() {
-            if &Utc::now() > t {
+    /// 上次运行时的节点，用于比较节点的变化
+    pub fn nodes_path(&self) -> PathBuf {
+        let mut path = self.cache.clone().into_os_string();
+        path.push(".nodes.json");
+        path.into()
+    }
+
+    fn meta_path(&self) -> PathBuf {
+        let mut path = self.cache.clone().into_os_string();
+        path.push(".meta.json");
+        path.into()
+    }
+
+    async fn read_meta(&self) -> Meta {
+        let meta = fs::read_to_string(self.meta_path())
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|s| Ok(serde_json::from_str(&s)?));
+        match meta {
+            Ok(meta) => meta,
+            Err(e) => {
+                debug!("no meta for {}: {:?}", self.name, e);
+                Meta::default()
+            }
+        }
+    }
+
+    /// 缓存中的流量信息
+    pub async fn cached_userinfo(&self) -> Option<UserInfo> {
+        self.read_meta().await.userinfo?.parse().ok()
+    }
+
+    /// 距上次确认缓存内容的时间，没有缓存时为 `None`
+    async fn cache_age(&self, meta: &Meta) -> Result<Option<Duration>> {
+        if !self.cache.exists() {
+            return Ok(None);
+        }
+        let age = match meta.checked_at {
+            Some(t) => (Utc::now().timestamp() - t).max(0) as u64,
+            None => {
+                let modified = fs::metadata(&self.cache).await?.modified()?;
+                SystemTime::now()
+                    .duration_since(modified)
+                    .unwrap_or_default()
+                    .as_secs()
+            }
+        };
+        Ok(Some(Duration::from_secs(age)))
+    }
+
+    /// 实际的到期时间，手动配置的 `expire` 优先于响应头
+    pub fn expire_at(&self, userinfo: Option<&UserInfo>) -> Option<DateTime<Utc>> {
+        self.expire
+            .map(|t| t.with_timezone(&Utc))
+            .or_else(|| userinfo.and_then(UserInfo::expire_at))
+    }
+
+    fn check_expire(&self, userinfo: Option<&UserInfo>) -> Result<()> {
+        if let Some(t) = self.expire_at(userinfo) {
+            if Utc::now() > t {
                 bail!("The subscription has expired, datetime = {}.", t);
             }
         }
+        Ok(())
+    }
 
-        if let Some(parent) = self.cache.parent() {
-            fs::create_dir_all(parent).await?;
+    fn fetched(&self, content: String, meta: Meta, source: Source) -> Result<Fetched> {
+        let userinfo = meta
+            .userinfo
+            .as_ref()
+            .and_then(|s| match s.parse::<UserInfo>() {
+                Ok(info) => Some(info),
+                Err(e) => {
+                    warn!(
+                        "机场 {} 的 {} 无法解析：{:?}",
+                        self.name,
+                        userinfo::HEADER,
+                        e
+                    );
+                    None
+                }
+            });
+        self.check_expire(userinfo.as_ref())?;
+        Ok(Fetched {
+            content,
+            userinfo,
+            meta,
+            source,
+            mirror: None,
+        })
+    }
+
+    /// 没有配置代理、User-Agent 和请求头时共用 `shared`，否则单独创建客户端
+    pub fn client(&self, shared: &request::Client) -> Result<request::Client> {
+        if self.proxy.is_none() && self.user_agent.is_none() && self.headers.is_empty() {
+            return Ok(shared.clone());
+        }
+        let mut headers = header::HeaderMap::new();
+        for (key, value) in &self.headers {
+            headers.insert(
+                header::HeaderName::from_bytes(key.as_bytes())?,
+                value.parse()?,
+            );
+        }
+        let mut builder = client_builder().default_headers(headers);
+        if let Some(user_agent) = &self.user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(request::Proxy::all(proxy)?);
         }
+        Ok(builder.build()?)
+    }
 
-        let cache_hit = self.cache.exists()
-            && (SystemTime::now().duration_since(fs::metadata(&self.cache).await?.modified()?)?
-                < std::time::Duration::from_secs(3600));
+    /// 缓存未过期时读取缓存，否则带上 `If-None-Match` / `If-Modified-Since` 重新请求。
+    /// 下载的内容不会立即写入缓存，解析成功后再调用 `save`
+    pub async fn get(&self, client: &request::Client) -> Result<Fetched> {
+        self.check_expire(None)?;
 
-        let content = if cache_hit {
+        let cached = self.read_meta().await;
+        let age = self.cache_age(&cached).await?;
+        if matches!(age, Some(age) if age < Duration::from_secs(self.ttl)) {
             debug!("cache hit, use file cache {:?}", self.cache);
-            fs::read_to_string(&self.cache).await?
-        } else {
-            debug!("downloading url for {}", self.name);
-            let r = request::get(&self.url).await?;
-            if r.status() != request::StatusCode::OK {
-                bail!("Status code = {}", r.status())
-            }
-            debug!("download success.");
-            let content = r.text().await?;
-            fs::write(&self.cache, &content).await?;
-            content
+            let content = fs::read_to_string(&self.cache).await?;
+            return self.fetched(content, cached, Source::Cache);
+        }
+
+        let mut retried = 0;
+        let (content, meta, source, mirror) = loop {
+            let e = match self.download_mirrors(client, &cached, age.is_some()).await {
+                Ok(downloaded) => break downloaded,
+                Err(e) => e,
+            };
+            if retried >= self.retries {
+                return Err(e);
+            }
+            // 1s, 2s, 4s, ...
+            let backoff = Duration::from_secs(1 << retried.min(6));
+            retried += 1;
+            warn!(
+                "机场 {} 第 {} 次下载失败，{} 秒后重试：{:?}",
+                self.name,
+                retried,
+                backoff.as_secs(),
+                e
+            );
+            time::sleep(backoff).await;
+        };
+        let mut fetched = self.fetched(content, meta, source)?;
+        fetched.mirror = mirror;
+        Ok(fetched)
+    }
+
+    /// 主地址和备用地址
+    pub fn urls(&self) -> impl Iterator<Item = &String> {
+        std::iter::once(&self.url).chain(&self.mirrors)
+    }
+
+    /// 用于展示的地址，只保留域名，避免泄露订阅的 token
+    pub fn url_host(&self, index: usize) -> String {
+        let url = self
+            .urls()
+            .nth(index)
+            .map(String::as_str)
+            .unwrap_or_default();
+        match request::Url::parse(url) {
+            Ok(url) => url.host_str().unwrap_or_default().to_string(),
+            Err(_) => format!("#{}", index),
+        }
+    }
+
+    /// 按顺序尝试各个地址，`race` 时同时请求所有地址。
+    /// 返回内容，以及主地址失败时下载成功的备用地址序号
+    async fn download_mirrors(
+        &self,
+        client: &request::Client,
+        cached: &Meta,
+        conditional: bool,
+    ) -> Result<(String, Meta, Source, Option<usize>)> {
+        let primary_failed = AtomicBool::new(false);
+        let attempt = |index: usize, url: &str| {
+            let url = url.to_string();
+            let primary_failed = &primary_failed;
+            async move {
+                let timeout = Duration::from_secs(self.timeout);
+                let result =
+                    time::timeout(timeout, self.download(client, &url, cached, conditional)).await;
+                if index == 0 && !matches!(result, Ok(Ok(_))) {
+                    primary_failed.store(true, Ordering::SeqCst);
+                }
+                match result {
+                    Ok(Ok((content, meta, source))) => Ok((content, meta, source, index)),
+                    Ok(Err(e)) => Err(e.context(format!("mirror #{} failed", index))),
+                    Err(_) => bail!("mirror #{} timed out after {} s", index, self.timeout),
+                }
+            }
         };
-        Ok(content)
+
+        if self.race && !self.mirrors.is_empty() {
+            let attempts = self
+                .urls()
+                .enumerate()
+                .map(|(index, url)| Box::pin(attempt(index, url)));
+            let ((content, meta, source, index), _) = future::select_ok(attempts).await?;
+            // 备用地址只是比主地址先返回时，不算作用了备用地址
+            let mirror = Some(index).filter(|_| primary_failed.load(Ordering::SeqCst));
+            return Ok((content, meta, source, mirror));
+        }
+
+        let mut last_error = None;
+        for (index, url) in self.urls().enumerate() {
+            match attempt(index, url).await {
+                Ok((content, meta, source, index)) => {
+                    return Ok((content, meta, source, Some(index).filter(|i| *i > 0)))
+                }
+                Err(e) => {
+                    if !self.mirrors.is_empty() {
+                        warn!(
+                            "机场 {} 的地址 {} 失败：{:?}",
+                            self.name,
+                            self.url_host(index),
+                            e
+                        );
+                    }
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| anyhow!("no url for {}", self.name)))
     }
+
+    /// 请求一次订阅，`conditional` 为真时带上缓存的 ETag / Last-Modified
+    async fn download(
+        &self,
+        client: &request::Client,
+        url: &str,
+        cached: &Meta,
+        conditional: bool,
+    ) -> Result<(String, Meta, Source)> {
+        debug!("downloading url for {}", self.name);
+        let mut request = client.get(url);
+        if conditional {
+            if let Some(etag) = &cached.etag {
+                request = request.header(header::IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &cached.last_modified {
+                request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+        let r = request.send().await?;
+        let header = |name: &str| {
+            r.headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        };
+        let meta = Meta {
+            userinfo: header(userinfo::HEADER),
+            etag: header(header::ETAG.as_str()),
+            last_modified: header(header::LAST_MODIFIED.as_str()),
+            checked_at: Some(Utc::now().timestamp()),
+        };
+
+        if r.status() == request::StatusCode::NOT_MODIFIED && conditional {
+            debug!("not modified, use file cache {:?}", self.cache);
+            let content = fs::read_to_string(&self.cache).await?;
+            // 304 的响应头可能不完整，缺的沿用缓存中的
+            let cached = cached.clone();
+            let meta = Meta {
+                userinfo: meta.userinfo.or(cached.userinfo),
+                etag: meta.etag.or(cached.etag),
+                last_modified: meta.last_modified.or(cached.last_modified),
+                checked_at: meta.checked_at,
+            };
+            return Ok((content, meta, Source::NotModified));
+        }
+        if r.status() != request::StatusCode::OK {
+            bail!("Status code = {}", r.status())
+        }
+        debug!("download success.");
+        let content = r.text().await?;
+        Ok((content, meta, Source::Download))
+    }
+
+    /// 把新下载的内容写入缓存；304 时只更新元信息
+    pub async fn save(&self, fetched: &Fetched) -> Result<()> {
+        if fetched.source == Source::Cache {
+            return Ok(());
+        }
+        if let Some(parent) = self.cache.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        if fetched.source == Source::Download {
+            fs::write(&self.cache, &fetched.content).await?;
+        }
+        fs::write(self.meta_path(), serde_json::to_string(&fetched.meta)?).await?;
+        Ok(())
+    }
+
+    /// 上次成功的缓存和它的年龄，用于下载或解析失败时兜底
+    pub async fn stale(&self) -> Result<(Fetched, Duration)> {
+        if self.stale_hours == 0 {
+            bail!("stale cache is disabled for {}", self.name);
+        }
+        let meta = self.read_meta().await;
+        let age = match self.cache_age(&meta).await? {
+            Some(age) => age,
+            None => bail!("no cache for {}", self.name),
+        };
+        if age > Duration::from_secs(self.stale_hours * 3600) {
+            bail!(
+                "cache is {} hours old, exceeds stale_hours = {}",
+                age.as_secs() / 3600,
+                self.stale_hours
+            );
+        }
+        let content = fs::read_to_string(&self.cache).await?;
+        let fetched = self.fetched(content, meta, Source::Cache)?;
+        Ok((fetched, age))
+    }
+}
+
+/// 测试用的订阅，`extra` 是额外的配置，没有配置 `name` / `cache` / `url` 时使用默认值
+#[cfg(test)]
+pub(super) fn subscription(extra: &str) -> Subscription {
+    let mut table: toml::value::Table = toml::from_str(extra).unwrap();
+    for &(key, value) in &[("name", "A"), ("cache", "cache"), ("url", "")] {
+        table.entry(key.to_string()).or_insert_with(|| value.into());
+    }
+    toml::Value::Table(table).try_into().unwrap()
+}
+
+/// 测试用的 HTTP 服务器，返回它的地址
+#[cfg(test)]
+pub(super) fn test_server<F, Fut>(handle: F) -> String
+where
+    F: Fn(hyper::Request<hyper::Body>) -> Fut + Clone + Send + Sync + 'static,
+    Fut: std::future::Future<Output = hyper::Response<hyper::Body>> + Send + 'static,
+{
+    use hyper::service::{make_service_fn, service_fn};
+    use std::convert::Infallible;
+
+    let make_service = make_service_fn(move |_| {
+        let handle = handle.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let response = handle(req);
+                async move { Ok::<_, Infallible>(response.await) }
+            }))
+        }
+    });
+    let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+    let url = format!("http://{}/", server.local_addr());
+    tokio::spawn(server);
+    url
+}
+
+#[test]
+fn test_parse_outputs() {
+    let s = r#"
+receiver = "someone@example.com"
+output = "legacy.txt"
+subscriptions = []
+
+[[outputs]]
+name = "full"
+path = "full.yaml"
+format = "clash"
+template = "rules.yaml"
+
+[[outputs]]
+name = "hk"
+path = "hk.txt"
+format = "plain"
+subscriptions = ["A"]
+include = { name = ["香港|HK"] }
+exclude = { protocol = ["ss"] }
+"#;
+    let config: Config = toml::from_str(s).unwrap();
+    let outputs = config.outputs();
+    assert_eq!(outputs.len(), 3);
+    assert_eq!(outputs[0].name, "default");
+    assert!(matches!(outputs[0].format, OutputFormat::Base64));
+    match &outputs[1].format {
+        OutputFormat::Clash(clash) => {
+            assert_eq!(clash.template, Some(PathBuf::from("rules.yaml")));
+            assert_eq!(clash.interval, 300);
+        }
+        _ => panic!("not clash"),
+    }
+    assert!(matches!(outputs[2].format, OutputFormat::Plain));
+    assert_eq!(outputs[2].subscriptions, vec!["A"]);
+    assert_eq!(outputs[2].include.name, vec!["香港|HK"]);
+    assert_eq!(outputs[2].exclude.protocol, vec!["ss"]);
+}
+
+#[test]
+fn test_duplicate_subscriptions() {
+    let s = r#"
+receiver = "someone@example.com"
+
+[[subscriptions]]
+name = "A"
+cache = "a.txt"
+url = ""
+
+[[subscriptions]]
+name = "A"
+cache = "b.txt"
+url = ""
+"#;
+    let e = toml::from_str::<Config>(s).unwrap_err();
+    assert!(e.to_string().contains("duplicate subscription name"));
+}
+
+#[tokio::test]
+async fn test_stale() {
+    let cache = std::env::temp_dir().join(format!("dtools-stale-{}.txt", std::process::id()));
+    let sub = |extra: &str| subscription(&format!("cache = {:?}\n{}", cache, extra));
+    assert!(sub("").stale().await.is_err());
+
+    fs::write(&cache, "content").await.unwrap();
+    let (fetched, age) = sub("").stale().await.unwrap();
+    assert_eq!(fetched.content, "content");
+    assert!(fetched.userinfo.is_none());
+    assert!(age < Duration::from_secs(60));
+    assert!(sub("stale_hours = 0").stale().await.is_err());
+    fs::remove_file(&cache).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_client() {
+    use hyper::{Body, Response};
+
+    // 返回请求的 User-Agent 和 X-Token
+    let url = test_server(|req| async move {
+        let header = |name: &str| {
+            req.headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default()
+                .to_string()
+        };
+        let body = format!("{} {}", header("user-agent"), header("x-token"));
+        Response::new(Body::from(body))
+    });
+    let text = |client: request::Client| {
+        let url = url.clone();
+        async move {
+            let r = client.get(&url).send().await.unwrap();
+            r.text().await.unwrap()
+        }
+    };
+
+    let shared = client_builder().build().unwrap();
+    let client = subscription("").client(&shared).unwrap();
+    assert_eq!(text(client).await.trim(), USER_AGENT);
+
+    let custom = subscription(
+        r#"
+user_agent = "clash-verge/v1.3.8"
+headers = { "X-Token" = "abc" }
+"#,
+    );
+    assert_eq!(custom.headers["X-Token"], "abc");
+    let client = custom.client(&shared).unwrap();
+    assert_eq!(text(client).await, "clash-verge/v1.3.8 abc");
+
+    assert!(subscription(r#"proxy = "socks5://127.0.0.1:1080""#)
+        .client(&shared)
+        .is_ok());
+    assert!(subscription(r#"headers = { "bad header" = "v" }"#)
+        .client(&shared)
+        .is_err());
+}
+
+#[test]
+fn test_mirrors() {
+    let sub = subscription(
+        r#"
+url = "https://a.example.com/sub?token=secret"
+mirrors = ["https://b.example.com/sub?token=secret", "not a url"]
+"#,
+    );
+    assert_eq!(sub.urls().count(), 3);
+    assert!(!sub.race);
+    assert_eq!(sub.url_host(1), "b.example.com");
+    assert_eq!(sub.url_host(2), "#2");
+}
+
+#[tokio::test]
+async fn test_conditional() {
+    use hyper::{Body, Response, StatusCode};
+    use std::sync::{Arc, Mutex};
+
+    // 记录每次请求的 If-None-Match
+    let requests = Arc::new(Mutex::new(vec![]));
+    let url = test_server({
+        let requests = requests.clone();
+        move |req| {
+            let etag = req
+                .headers()
+                .get(header::IF_NONE_MATCH)
+                .map(|v| v.to_str().unwrap().to_string());
+            requests.lock().unwrap().push(etag.clone());
+            let response = if etag.as_deref() == Some("\"v1\"") {
+                // 304 不带流量信息，应沿用缓存中的
+                Response::builder()
+                    .status(StatusCode::NOT_MODIFIED)
+                    .body(Body::empty())
+            } else {
+                Response::builder()
+                    .header(header::ETAG, "\"v1\"")
+                    .header(userinfo::HEADER, "upload=1; download=2; total=3")
+                    .body(Body::from("content"))
+            };
+            async move { response.unwrap() }
+        }
+    });
+
+    let cache = std::env::temp_dir().join(format!("dtools-conditional-{}.txt", std::process::id()));
+    let sub = |ttl: u64| {
+        subscription(&format!(
+            "cache = {:?}\nurl = {:?}\nttl = {}",
+            cache, url, ttl
+        ))
+    };
+    let client = client_builder().build().unwrap();
+
+    let fetched = sub(0).get(&client).await.unwrap();
+    assert_eq!(fetched.source, Source::Download);
+    assert_eq!(fetched.content, "content");
+    sub(0).save(&fetched).await.unwrap();
+    let checked_at = sub(0).read_meta().await.checked_at.unwrap();
+
+    let fetched = sub(0).get(&client).await.unwrap();
+    assert_eq!(fetched.source, Source::NotModified);
+    assert_eq!(fetched.content, "content");
+    assert_eq!(fetched.userinfo.unwrap().total, 3);
+    // 304 时不重写缓存，只更新元信息
+    fs::write(&cache, "changed").await.unwrap();
+    sub(0).save(&fetched).await.unwrap();
+    assert_eq!(fs::read_to_string(&cache).await.unwrap(), "changed");
+    let meta = sub(0).read_meta().await;
+    assert_eq!(meta.etag.as_deref(), Some("\"v1\""));
+    assert!(meta.userinfo.is_some());
+    assert!(meta.checked_at.unwrap() >= checked_at);
+
+    // 未超过 ttl 时不请求服务器
+    let fetched = sub(3600).get(&client).await.unwrap();
+    assert_eq!(fetched.source, Source::Cache);
+    assert_eq!(fetched.content, "changed");
+    assert_eq!(
+        *requests.lock().unwrap(),
+        vec![None, Some("\"v1\"".to_string())]
+    );
+
+    fs::remove_file(&cache).await.unwrap();
+    fs::remove_file(sub(0).meta_path()).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_fallback() {
+    use hyper::{Body, Response, StatusCode};
+
+    // `/fail` 返回 500，`/slow` 稍后返回，其余立即返回
+    let url = test_server(|req| async move {
+        match req.uri().path() {
+            "/fail" => {
+                let mut response = Response::new(Body::empty());
+                *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                return response;
+            }
+            "/slow" => time::sleep(Duration::from_millis(500)).await,
+            _ => {}
+        }
+        Response::new(Body::from(req.uri().path().to_string()))
+    });
+    let client = client_builder().build().unwrap();
+    let cache = std::env::temp_dir().join(format!("dtools-fallback-{}.txt", std::process::id()));
+    let get = |primary: &str, mirrors: &[&str], race: bool| {
+        let mirrors: Vec<String> = mirrors.iter().map(|m| format!("{}{}", url, m)).collect();
+        let sub = subscription(&format!(
+            "cache = {:?}\nurl = \"{}{}\"\nmirrors = {:?}\nrace = {}\nretries = 0",
+            cache, url, primary, mirrors, race
+        ));
+        let client = client.clone();
+        async move { sub.get(&client).await.unwrap() }
+    };
+
+    // 按顺序尝试，主地址失败后依次尝试备用地址
+    let fetched = get("fail", &["fail", "b"], false).await;
+    assert_eq!(fetched.content, "/b");
+    assert_eq!(fetched.mirror, Some(2));
+    let fetched = get("a", &["b"], false).await;
+    assert_eq!(fetched.content, "/a");
+    assert_eq!(fetched.mirror, None);
+
+    // 同时请求时，主地址没有失败就不报告备用地址
+    let fetched = get("slow", &["b"], true).await;
+    assert_eq!(fetched.content, "/b");
+    assert_eq!(fetched.mirror, None);
+    let fetched = get("fail", &["slow"], true).await;
+    assert_eq!(fetched.content, "/slow");
+    assert_eq!(fetched.mirror, Some(1));
 }
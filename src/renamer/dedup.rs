@@ -0,0 +1,106 @@
+//! 去掉不同机场之间重复的节点
+use super::parse::{Airport, Node};
+use std::collections::HashMap;
+
+/// 被去掉的重复节点
+#[derive(Debug)]
+pub struct Duplicate {
+    pub airport: String,
+    pub name: String,
+    /// 保留下来的同一节点
+    pub kept_airport: String,
+    pub kept_name: String,
+}
+
+/// 去重的依据：协议、地址、端口和凭据都相同视为同一个节点
+fn key(node: &Node) -> Option<(&'static str, String, u16, String)> {
+    let (server, port) = node.address().ok()?;
+    let credential = node.credential().ok()?;
+    Some((
+        node.protocol(),
+        server.to_ascii_lowercase(),
+        port,
+        credential,
+    ))
+}
+
+/// 按 `prefer` 中机场的顺序优先保留，其余按订阅的顺序保留第一个。
+/// 只去掉和其他机场重复的节点，同一机场内的重复节点保留；
+/// 原样保留的节点和解析不出地址的节点不参与去重
+pub fn dedup(airports: &mut [Airport], prefer: &[String]) -> Vec<Duplicate> {
+    let mut order: Vec<usize> = (0..airports.len()).collect();
+    // 稳定排序，不在 `prefer` 中的机场排在最后，保持原有顺序
+    order.sort_by_key(|&i| {
+        prefer
+            .iter()
+            .position(|name| name == &airports[i].name)
+            .unwrap_or(prefer.len())
+    });
+
+    let mut seen: HashMap<_, (String, String)> = HashMap::new();
+    let mut duplicates = vec![];
+    for i in order {
+        let airport = &mut airports[i];
+        for (node, region) in airport.take_nodes() {
+            let name = node.name().unwrap_or_default();
+            match key(&node) {
+                Some(key) => match seen.get(&key) {
+                    Some((kept_airport, kept_name)) if *kept_airport != airport.name => {
+                        debug!("duplicate node {} in {}", name, airport.name);
+                        duplicates.push(Duplicate {
+                            airport: airport.name.clone(),
+                            name,
+                            kept_airport: kept_airport.clone(),
+                            kept_name: kept_name.clone(),
+                        });
+                    }
+                    Some(_) => airport.push(node, region),
+                    None => {
+                        seen.insert(key, (airport.name.clone(), name));
+                        airport.push(node, region);
+                    }
+                },
+                None => airport.push(node, region),
+            }
+        }
+    }
+    duplicates
+}
+
+#[test]
+fn test_dedup() {
+    use super::config::Format;
+
+    let a = [
+        "trojan://p@a.example.com:443#A1",
+        "trojan://p@b.example.com:443#A2",
+        "trojan://p@b.example.com:443#A3",
+    ];
+    let b = [
+        "trojan://p@A.example.com:443#B1",
+        "trojan://q@a.example.com:443#B2",
+    ];
+    let airports = vec![
+        Airport::new("A", a.join("\n"), Format::Auto, false).unwrap(),
+        Airport::new("B", b.join("\n"), Format::Auto, false).unwrap(),
+    ];
+    let names = |airports: &[Airport]| -> Vec<Vec<String>> {
+        airports
+            .iter()
+            .map(|a| a.nodes.iter().map(|n| n.name().unwrap()).collect())
+            .collect()
+    };
+
+    let mut first = airports.clone();
+    let duplicates = dedup(&mut first, &[]);
+    // A3 和 A2 在同一个机场，不算重复
+    assert_eq!(names(&first), vec![vec!["A1", "A2", "A3"], vec!["B2"]]);
+    assert_eq!(duplicates.len(), 1);
+    assert_eq!(duplicates[0].name, "B1");
+    assert_eq!(duplicates[0].kept_name, "A1");
+
+    let mut preferred = airports;
+    let duplicates = dedup(&mut preferred, &["B".to_string()]);
+    assert_eq!(names(&preferred), vec![vec!["A2", "A3"], vec!["B1", "B2"]]);
+    assert_eq!(duplicates[0].kept_airport, "B");
+}
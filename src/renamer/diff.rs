@@ -0,0 +1,142 @@
+//! 和上次运行相比，机场节点的变化
+use super::parse::{Airport, Node};
+use anyhow::Result;
+use std::{collections::BTreeMap, fmt, path::Path};
+use tokio::fs;
+
+/// 一个机场的节点：节点的标识到原始节点名
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    nodes: BTreeMap<String, String>,
+}
+
+/// 协议、地址、端口和凭据相同视为同一个节点，凭据只保存摘要
+fn key(node: &Node) -> String {
+    match (node.address(), node.credential()) {
+        (Ok((server, port)), Ok(credential)) => format!(
+            "{} {}:{} {:x}",
+            node.protocol(),
+            server.to_ascii_lowercase(),
+            port,
+            md5::compute(credential)
+        ),
+        _ => format!("{:x}", md5::compute(node.to_string())),
+    }
+}
+
+impl Snapshot {
+    /// 应在重命名之前调用，记录机场给的原始节点名
+    pub fn new(airport: &Airport) -> Self {
+        let nodes = airport
+            .nodes
+            .iter()
+            .map(|node| {
+                let name = node.name().unwrap_or_else(|_| node.to_string());
+                (key(node), name)
+            })
+            .collect();
+        Self { nodes }
+    }
+
+    /// 上次保存的快照，不存在时为 `None`
+    pub async fn load(path: &Path) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let s = fs::read_to_string(path).await?;
+        Ok(Some(serde_json::from_str(&s)?))
+    }
+
+    pub async fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)?).await?;
+        Ok(())
+    }
+
+    /// 从 `previous` 到现在的变化
+    pub fn diff(&self, previous: &Snapshot) -> Diff {
+        let mut diff = Diff::default();
+        for (key, name) in &self.nodes {
+            match previous.nodes.get(key) {
+                None => diff.added.push(name.clone()),
+                Some(old) if old != name => diff.changed.push((old.clone(), name.clone())),
+                Some(_) => {}
+            }
+        }
+        for (key, name) in &previous.nodes {
+            if !self.nodes.contains_key(key) {
+                diff.removed.push(name.clone());
+            }
+        }
+        diff
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Diff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// 改名的节点，旧名字和新名字
+    pub changed: Vec<(String, String)>,
+}
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "新增 {} 个，移除 {} 个，改名 {} 个",
+            self.added.len(),
+            self.removed.len(),
+            self.changed.len()
+        )?;
+        for name in &self.added {
+            writeln!(f, "  + {}", name)?;
+        }
+        for name in &self.removed {
+            writeln!(f, "  - {}", name)?;
+        }
+        for (old, new) in &self.changed {
+            writeln!(f, "  ~ {} → {}", old, new)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_diff() {
+    use super::config::Format;
+
+    let snapshot = |links: &[&str]| {
+        let airport = Airport::new("A", links.join("\n"), Format::Auto, false).unwrap();
+        Snapshot::new(&airport)
+    };
+    let previous = snapshot(&[
+        "trojan://p@a.example.com:443#A",
+        "trojan://p@b.example.com:443#B",
+        "trojan://p@c.example.com:443#C",
+    ]);
+    let current = snapshot(&[
+        "trojan://p@A.example.com:443#A",
+        "trojan://p@b.example.com:443#B2",
+        "trojan://p@d.example.com:443#D",
+    ]);
+    let diff = current.diff(&previous);
+    assert_eq!(diff.added, vec!["D"]);
+    assert_eq!(diff.removed, vec!["C"]);
+    assert_eq!(diff.changed, vec![("B".to_string(), "B2".to_string())]);
+    assert!(current.diff(&current).is_empty());
+    assert_eq!(
+        diff.to_string(),
+        "新增 1 个，移除 1 个，改名 1 个\n  + D\n  - C\n  ~ B → B2\n"
+    );
+
+    let json = serde_json::to_string(&current).unwrap();
+    assert_eq!(serde_json::from_str::<Snapshot>(&json).unwrap(), current);
+}
@@ -0,0 +1,102 @@
+//! 按名字、服务器、端口和协议过滤节点
+use super::parse::Node;
+use anyhow::Result;
+use regex::Regex;
+
+/// 过滤规则，各字段都是正则，任一字段中的任一正则匹配即视为匹配
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct Filter {
+    /// 订阅中原始的节点名
+    #[serde(default)]
+    pub name: Vec<String>,
+    #[serde(default)]
+    pub server: Vec<String>,
+    #[serde(default)]
+    pub port: Vec<String>,
+    /// 协议名，如 `vmess`、`ss`、`hysteria2`
+    #[serde(default)]
+    pub protocol: Vec<String>,
+}
+impl Filter {
+    /// 默认排除的节点：机场用来显示流量、官网等信息的假节点
+    pub fn junk() -> Self {
+        Self {
+            name: vec!["剩余|规则|购买|收入|流量|过期|链接|官网|域名".to_string()],
+            ..Self::default()
+        }
+    }
+}
+
+/// 编译好的过滤规则
+#[derive(Debug)]
+pub struct Matcher {
+    name: Vec<Regex>,
+    server: Vec<Regex>,
+    port: Vec<Regex>,
+    protocol: Vec<Regex>,
+}
+impl Matcher {
+    pub fn new(filter: &Filter) -> Result<Self> {
+        let compile = |patterns: &[String]| -> Result<Vec<Regex>> {
+            Ok(patterns
+                .iter()
+                .map(|r| Regex::new(r))
+                .collect::<Result<Vec<Regex>, _>>()?)
+        };
+        Ok(Self {
+            name: compile(&filter.name)?,
+            server: compile(&filter.server)?,
+            port: compile(&filter.port)?,
+            protocol: compile(&filter.protocol)?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.name.is_empty()
+            && self.server.is_empty()
+            && self.port.is_empty()
+            && self.protocol.is_empty()
+    }
+
+    /// 原样保留的节点没有名字和地址，总是不匹配
+    pub fn matches(&self, node: &Node) -> bool {
+        let any = |regexps: &[Regex], s: &str| regexps.iter().any(|r| r.is_match(s));
+        if let Ok(name) = node.name() {
+            if any(&self.name, &name) {
+                return true;
+            }
+        }
+        if let Ok((server, port)) = node.address() {
+            if any(&self.server, &server) || any(&self.port, &port.to_string()) {
+                return true;
+            }
+        }
+        !node.is_opaque() && any(&self.protocol, node.protocol())
+    }
+}
+
+#[test]
+fn test_matches() {
+    let node: Node = "trojan://p@hk.example.com:443#%E9%A6%99%E6%B8%AF%2001"
+        .parse()
+        .unwrap();
+    let junk: Node = "trojan://p@1.2.3.4:443#%E5%89%A9%E4%BD%99%E6%B5%81%E9%87%8F"
+        .parse()
+        .unwrap();
+    let matcher = Matcher::new(&Filter::junk()).unwrap();
+    assert!(!matcher.matches(&node));
+    assert!(matcher.matches(&junk));
+
+    let filter: Filter = toml::from_str(
+        r#"
+server = ['\.example\.com$']
+port = ["^80$"]
+protocol = ["^vmess$"]
+"#,
+    )
+    .unwrap();
+    let matcher = Matcher::new(&filter).unwrap();
+    assert!(matcher.matches(&node));
+    assert!(!matcher.matches(&junk));
+    assert!(Matcher::new(&Filter::default()).unwrap().is_empty());
+}
@@ -1,26 +1,208 @@
+mod clash;
 mod config;
+mod dedup;
+mod diff;
+mod filter;
+mod output;
 mod parse;
+mod probe;
+mod region;
+mod serve;
+mod singbox;
+mod sip008;
+mod surge;
+mod userinfo;
+mod view;
 
 pub use config::Config;
+pub use serve::main as serve;
 
 use anyhow::Result;
-use config::Subscription;
-use std::{collections::BTreeMap, path::PathBuf};
-use tokio::{
-    fs,
-    io::{AsyncWriteExt, BufWriter},
-};
+use chrono::{DateTime, Utc};
+use std::{collections::BTreeMap, time::Duration};
 
 use crate::notifier::Notifier;
+use config::{Fetched, Subscription};
+use futures_util::stream::{self, StreamExt};
 
-async fn run(
-    output: PathBuf,
-    subscriptions: Vec<Subscription>,
-) -> Result<BTreeMap<String, Result<usize>>> {
+/// 单个机场的转换结果
+#[derive(Debug)]
+pub struct Report {
+    /// 重命名后输出的节点数
+    pub renamed: usize,
+    /// 无法识别、原样输出的节点数
+    pub passthrough: usize,
+    /// 与其他机场重复而去掉的节点数
+    pub duplicated: usize,
+    /// 各个过滤规则去掉的节点数，没有去掉节点的规则不记录
+    pub filtered: Vec<(&'static str, usize)>,
+    /// 响应头中的流量信息
+    pub userinfo: Option<userinfo::UserInfo>,
+    /// 到期时间
+    pub expire: Option<DateTime<Utc>>,
+    /// 下载或解析失败、使用了旧缓存时，缓存的年龄
+    pub stale: Option<Duration>,
+    /// 主地址失败、从备用地址下载成功时，备用地址的域名
+    pub mirror: Option<String>,
+    /// 和上次运行相比节点的变化，第一次运行时为 `None`
+    pub diff: Option<diff::Diff>,
+}
+impl Report {
+    pub fn total(&self) -> usize {
+        self.renamed + self.passthrough
+    }
+}
+
+/// 单个输出的结果
+#[derive(Debug)]
+pub struct OutputReport {
+    pub nodes: usize,
+    /// 无法转换为目标格式而跳过的节点及原因
+    pub skipped: Vec<String>,
+}
+
+/// 一次运行的结果
+#[derive(Debug)]
+pub struct Summary {
+    pub airports: BTreeMap<String, Result<Report>>,
+    /// 去重时去掉的节点
+    pub duplicates: Vec<dedup::Duplicate>,
+    /// 节点探测的结果，没有配置探测时为 `None`
+    pub probe: Option<probe::ProbeReport>,
+    /// 按配置顺序排列的输出
+    pub outputs: Vec<(String, Result<OutputReport>)>,
+}
+
+/// 获取并解析订阅，解析成功后才写入缓存。
+/// 机场出错时常常返回空内容或 HTML 页面，解析不出任何节点时视为失败，不覆盖上次的缓存。
+/// `save` 为 false 时（`rename --diff`）不写缓存
+async fn fetch(
+    sub: &Subscription,
+    client: &request::Client,
+    save: bool,
+) -> Result<(Fetched, parse::Airport)> {
+    let client = sub.client(client)?;
+    let fetched = sub.get(&client).await?;
+    let airport = parse::Airport::new(&sub.name, &fetched.content, sub.format, sub.passthrough)?;
+    if airport.nodes.len() == airport.passthrough_count() {
+        bail!(
+            "no node parsed from {} bytes of content",
+            fetched.content.len()
+        );
+    }
+    if save {
+        sub.save(&fetched).await?;
+    }
+    Ok((fetched, airport))
+}
+
+/// 下载或解析失败时，退回到上次成功的缓存
+async fn fallback(sub: &Subscription) -> Result<(Fetched, parse::Airport, Duration)> {
+    let (fetched, age) = sub.stale().await?;
+    let airport = parse::Airport::new(&sub.name, &fetched.content, sub.format, sub.passthrough)?;
+    Ok((fetched, airport, age))
+}
+
+/// 获取并解析订阅，失败时尝试旧缓存；都失败时返回原本的错误
+async fn load(
+    sub: &Subscription,
+    client: &request::Client,
+    save: bool,
+) -> Result<(Fetched, parse::Airport, Option<Duration>)> {
+    let e = match fetch(sub, client, save).await {
+        Ok((fetched, airport)) => return Ok((fetched, airport, None)),
+        Err(e) => e,
+    };
+    match fallback(sub).await {
+        Ok((fetched, airport, age)) => {
+            warn!(
+                "机场 {} 失败，使用 {} 秒前的缓存：{:?}",
+                sub.name,
+                age.as_secs(),
+                e
+            );
+            Ok((fetched, airport, Some(age)))
+        }
+        Err(fallback_error) => {
+            debug!("fallback failed for {}: {:?}", sub.name, fallback_error);
+            Err(e)
+        }
+    }
+}
+
+/// 并发下载所有订阅，结果按订阅的顺序排列
+async fn load_all(
+    config: &Config,
+    client: &request::Client,
+    save: bool,
+) -> Vec<Result<(Fetched, parse::Airport, Option<Duration>)>> {
+    let loads: Vec<_> = config
+        .subscriptions
+        .iter()
+        .map(|sub| load(sub, client, save))
+        .collect();
+    stream::iter(loads)
+        .buffered(config.concurrency.max(1))
+        .collect()
+        .await
+}
+
+/// 和上次运行时的节点比较，`save` 为真时保存这次的节点
+async fn compare(sub: &Subscription, airport: &parse::Airport, save: bool) -> Option<diff::Diff> {
+    let path = sub.nodes_path();
+    let snapshot = diff::Snapshot::new(airport);
+    let diff = match diff::Snapshot::load(&path).await {
+        Ok(previous) => previous.map(|previous| snapshot.diff(&previous)),
+        Err(e) => {
+            warn!("无法读取机场 {} 上次的节点：{:?}", sub.name, e);
+            None
+        }
+    };
+    if save {
+        if let Err(e) = snapshot.save(&path).await {
+            warn!("无法保存机场 {} 的节点：{:?}", sub.name, e);
+        }
+    }
+    diff
+}
+
+/// 一次运行除了比较节点的变化之外还要做的事
+#[derive(Debug, Clone, Copy)]
+struct Mode {
+    /// 探测节点、写入输出，并更新订阅缓存
+    write: bool,
+    /// 保存这次的节点快照，作为下次比较的基准
+    snapshot: bool,
+}
+impl Mode {
+    /// `rename` 子命令
+    const RENAME: Self = Self {
+        write: true,
+        snapshot: true,
+    };
+    /// `rename --diff`，只比较，不改动任何文件
+    const DIFF: Self = Self {
+        write: false,
+        snapshot: false,
+    };
+    /// `serve` 按需重新生成输出，节点的变化留给 `rename` 比较和通知
+    const SERVE: Self = Self {
+        write: true,
+        snapshot: false,
+    };
+}
+
+async fn run(config: &Config, mode: Mode) -> Result<Summary> {
     let mut results = BTreeMap::new();
-    let mut nodes = vec![];
+    let mut airports = vec![];
+    let regions = region::Regions::new(&config.regions);
+    let include = filter::Matcher::new(&config.include)?;
+    let exclude = filter::Matcher::new(&config.exclude)?;
 
-    for sub in subscriptions {
+    let client = config::client_builder().build()?;
+    let loaded = load_all(config, &client, mode.write).await;
+
+    for (sub, result) in config.subscriptions.iter().zip(loaded) {
         macro_rules! check {
             ($r:expr) => {
                 match $r {
@@ -33,32 +215,126 @@ async fn run(
                 }
             };
         }
-        let content = check!(sub.get().await);
-        let mut airport = check!(parse::Airport::new(&sub.name, content));
-        check!(airport.rename(&sub.replacements));
+        let (fetched, mut airport, stale) = check!(result);
+        let sub_include = check!(filter::Matcher::new(&sub.include));
+        let sub_exclude = check!(filter::Matcher::new(&sub.exclude));
+        let filtered: Vec<_> = vec![
+            ("全局 include", airport.filter(&include, true)),
+            ("全局 exclude", airport.filter(&exclude, false)),
+            ("include", airport.filter(&sub_include, true)),
+            ("exclude", airport.filter(&sub_exclude, false)),
+        ]
+        .into_iter()
+        .filter(|(_, n)| *n > 0)
+        .collect();
+        let diff = compare(sub, &airport, mode.snapshot).await;
+        check!(airport.rename(sub, &regions));
 
         // output
-        info!("{} has {} nodes.", airport.name, airport.nodes.len());
-        results.insert(airport.name, Ok(airport.nodes.len()));
-        nodes.extend(airport.nodes);
+        let passthrough = airport.passthrough_count();
+        let report = Report {
+            renamed: airport.nodes.len() - passthrough,
+            passthrough,
+            duplicated: 0,
+            filtered,
+            stale,
+            mirror: fetched.mirror.map(|index| sub.url_host(index)),
+            expire: sub.expire_at(fetched.userinfo.as_ref()),
+            userinfo: fetched.userinfo,
+            diff,
+        };
+        info!(
+            "{} has {} nodes, {} passed through.",
+            airport.name,
+            airport.nodes.len(),
+            passthrough
+        );
+        results.insert(airport.name.clone(), Ok(report));
+        airports.push(airport);
+    }
+    // dedup
+    let duplicates = match &config.dedup {
+        Some(options) => dedup::dedup(&mut airports, &options.prefer),
+        None => vec![],
+    };
+    for duplicate in &duplicates {
+        if let Some(Ok(report)) = results.get_mut(&duplicate.airport) {
+            report.duplicated += 1;
+        }
+    }
+    if !duplicates.is_empty() {
+        info!("{} duplicate nodes removed.", duplicates.len());
+    }
+    let renamed = parse::unique_names(&mut airports);
+    if renamed > 0 {
+        info!(
+            "{} nodes renamed to avoid duplicate names across airports.",
+            renamed
+        );
+    }
+    // probe
+    let probe = match &config.probe {
+        Some(options) if mode.write => {
+            let report = probe::probe(&mut airports, options).await;
+            info!(
+                "{} nodes reachable, {} unreachable, {} skipped.",
+                report.reachable,
+                report.unreachable.len(),
+                report.skipped
+            );
+            Some(report)
+        }
+        _ => None,
+    };
+    // 去重和探测都会去掉节点，按最终的节点数更新
+    for airport in &airports {
+        if let Some(Ok(report)) = results.get_mut(&airport.name) {
+            report.passthrough = airport.passthrough_count();
+            report.renamed = airport.nodes.len() - report.passthrough;
+        }
     }
     // write
-    let nodes_encoded = base64::encode(
-        nodes
-            .iter()
-            .map(|node| node.to_string())
-            .collect::<Vec<_>>()
-            .join("\n"),
-    );
-    let mut output_file = BufWriter::new(fs::File::create(output).await?);
-    output_file.write_all(nodes_encoded.as_bytes()).await?;
+    let mut outputs = vec![];
+    for output in config.outputs().into_iter().filter(|_| mode.write) {
+        let result = output::generate(&output, &airports).await;
+        match &result {
+            Ok(report) => info!("output {} has {} nodes.", output.name, report.nodes),
+            Err(e) => warn!("输出 {} 失败：{:?}", output.name, e),
+        }
+        outputs.push((output.name, result));
+    }
 
     info!("done.");
-    Ok(results)
+    Ok(Summary {
+        airports: results,
+        duplicates,
+        probe,
+        outputs,
+    })
 }
 
-pub async fn main(notifier: Notifier, config: Config) -> Result<()> {
-    let results = run(config.output, config.subscriptions).await;
+/// 打印各机场节点的变化，不写入输出
+async fn print_diff(config: &Config) -> Result<()> {
+    let summary = run(config, Mode::DIFF).await?;
+    for (name, result) in &summary.airports {
+        match result {
+            Ok(report) => match &report.diff {
+                Some(diff) if diff.is_empty() => println!("机场 {}：没有变化", name),
+                Some(diff) => print!("机场 {}：{}", name, diff),
+                None => println!("机场 {}：没有上次的节点", name),
+            },
+            Err(e) => println!("机场 {} 失败：{}", name, e),
+        }
+    }
+    Ok(())
+}
+
+/// `diff` 为真时只打印节点的变化
+pub async fn main(notifier: Notifier, config: Config, diff: bool) -> Result<()> {
+    if diff {
+        return print_diff(&config).await;
+    }
+    let results = run(&config, Mode::RENAME).await;
 
     match results {
         Err(e) => {
@@ -70,17 +346,28 @@ pub async fn main(notifier: Notifier, config: Config) -> Result<()> {
                 )
                 .await?;
         }
-        Ok(results) => {
-            let total_nodes: usize = results.values().filter_map(|r| r.as_ref().ok()).sum();
+        Ok(summary) => {
+            let results = &summary.airports;
+            let total_nodes: usize = results
+                .values()
+                .filter_map(|r| r.as_ref().ok())
+                .map(Report::total)
+                .sum();
             let total_failed = results.values().filter(|r| r.is_err()).count();
+            let outputs_failed = summary.outputs.iter().filter(|(_, r)| r.is_err()).count();
 
-            let title = if total_failed == 0 {
+            let title = if total_failed == 0 && outputs_failed == 0 {
                 format!("转换订阅链接成功，共 {} 个订阅", total_nodes)
-            } else {
+            } else if outputs_failed == 0 {
                 format!(
                     "转换订阅链接部分成功，共 {} 个订阅，{} 个机场失败",
                     total_nodes, total_failed
                 )
+            } else {
+                format!(
+                    "转换订阅链接部分成功，共 {} 个订阅，{} 个机场失败，{} 个输出失败",
+                    total_nodes, total_failed, outputs_failed
+                )
             };
 
             let ok = results
@@ -91,13 +378,111 @@ pub async fn main(notifier: Notifier, config: Config) -> Result<()> {
                 .filter_map(|(k, v)| Some((k, v.as_ref().err()?)));
 
             let mut body = String::new();
-            for (name, cnt) in ok {
-                body += &format!("机场 {} 成功，共 {} 个节点\n", name, cnt);
+            let warnings = warnings(&config, &summary);
+            if !warnings.is_empty() {
+                for warning in &warnings {
+                    body += &format!("⚠️ {}\n", warning);
+                }
+                body += "\n";
+            }
+            for (name, report) in ok {
+                body += &format!("机场 {} 成功，共 {} 个节点", name, report.total());
+                if let Some(mirror) = &report.mirror {
+                    body += &format!("（使用备用地址 {}）", mirror);
+                }
+                if let Some(age) = report.stale {
+                    body += &format!(
+                        "（更新失败，使用 {:.1} 小时前的缓存）",
+                        age.as_secs_f64() / 3600.0
+                    );
+                }
+                if report.passthrough > 0 {
+                    body += &format!(
+                        "（重命名 {} 个，原样保留 {} 个）",
+                        report.renamed, report.passthrough
+                    );
+                }
+                if let Some(info) = &report.userinfo {
+                    if info.total > 0 {
+                        body += &format!(
+                            "，剩余流量 {} / {}",
+                            userinfo::format_bytes(info.remaining()),
+                            userinfo::format_bytes(info.total)
+                        );
+                    }
+                }
+                if let Some(expire) = &report.expire {
+                    body += &format!(
+                        "，{} 到期",
+                        expire.with_timezone(&chrono::Local).format("%Y-%m-%d")
+                    );
+                }
+                if report.duplicated > 0 {
+                    body += &format!("，去重 {} 个", report.duplicated);
+                }
+                if !report.filtered.is_empty() {
+                    let filtered: Vec<String> = report
+                        .filtered
+                        .iter()
+                        .map(|(filter, n)| format!("{} {} 个", filter, n))
+                        .collect();
+                    body += &format!("，过滤掉 {}", filtered.join("，"));
+                }
+                body += "\n";
             }
             body += "\n\n";
             for (name, e) in err {
                 body += &format!("机场 {} 失败：{}\n详细原因：{:?}\n\n", name, e, e);
             }
+            if !summary.duplicates.is_empty() {
+                body += &format!("去掉 {} 个重复节点：\n", summary.duplicates.len());
+                for d in &summary.duplicates {
+                    body += &format!(
+                        "  - {}（{}）与 {}（{}）重复\n",
+                        d.name, d.airport, d.kept_name, d.kept_airport
+                    );
+                }
+                body += "\n";
+            }
+            if let Some(probe) = &summary.probe {
+                body += &format!(
+                    "探测节点：{} 个可达，{} 个不可达，{} 个无法探测\n",
+                    probe.reachable,
+                    probe.unreachable.len(),
+                    probe.skipped
+                );
+                for unreachable in &probe.unreachable {
+                    body += &format!("  - {}\n", unreachable);
+                }
+                body += "\n";
+            }
+            let diffs: Vec<_> = summary
+                .airports
+                .iter()
+                .filter_map(|(name, r)| Some((name, r.as_ref().ok()?.diff.as_ref()?)))
+                .filter(|(_, diff)| !diff.is_empty())
+                .collect();
+            if !diffs.is_empty() {
+                body += "节点变化：\n";
+                for (name, diff) in diffs {
+                    body += &format!("机场 {}：{}", name, diff);
+                }
+                body += "\n";
+            }
+            for (name, result) in &summary.outputs {
+                match result {
+                    Ok(report) => {
+                        body += &format!("输出 {} 成功，共 {} 个节点\n", name, report.nodes);
+                        if !report.skipped.is_empty() {
+                            body += &format!("跳过 {} 个节点：\n", report.skipped.len());
+                            for skipped in &report.skipped {
+                                body += &format!("  - {}\n", skipped);
+                            }
+                        }
+                    }
+                    Err(e) => body += &format!("输出 {} 失败：{}\n详细原因：{:?}\n", name, e, e),
+                }
+            }
 
             if std::env::var("DRYRUN").is_err() {
                 notifier.notify(&config.receiver, title, body).await?;
@@ -107,3 +492,230 @@ pub async fn main(notifier: Notifier, config: Config) -> Result<()> {
 
     Ok(())
 }
+
+/// 流量快用完或快到期的机场
+fn warnings(config: &Config, summary: &Summary) -> Vec<String> {
+    let now = Utc::now();
+    let mut warnings = vec![];
+    for (name, report) in summary.airports.iter() {
+        let report = match report {
+            Ok(report) => report,
+            Err(_) => continue,
+        };
+        let usage = report.userinfo.as_ref().and_then(|info| info.usage());
+        if let Some(usage) = usage.filter(|usage| *usage >= config.warning.usage) {
+            warnings.push(format!(
+                "机场 {} 已用流量 {:.0}%，剩余 {}",
+                name,
+                usage * 100.0,
+                userinfo::format_bytes(report.userinfo.unwrap_or_default().remaining())
+            ));
+        }
+        if let Some(expire) = report.expire {
+            let days = (expire - now).num_days();
+            if days < config.warning.expire_days {
+                warnings.push(format!("机场 {} 将在 {} 天后到期", name, days));
+            }
+        }
+    }
+    warnings
+}
+
+#[tokio::test]
+async fn test_load_all() {
+    use hyper::{Body, Response, StatusCode};
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Instant,
+    };
+
+    // `/stall` 一直不返回，`/flaky` 第一次返回 500，其余立即返回
+    let flaky = Arc::new(AtomicUsize::new(0));
+    let url = config::test_server(move |req| {
+        let flaky = flaky.clone();
+        async move {
+            match req.uri().path() {
+                "/stall" => tokio::time::sleep(Duration::from_secs(30)).await,
+                "/flaky" if flaky.fetch_add(1, Ordering::SeqCst) == 0 => {
+                    let mut response = Response::new(Body::empty());
+                    *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                    return response;
+                }
+                _ => {}
+            }
+            Response::new(Body::from("trojan://p@1.2.3.4:443#a"))
+        }
+    });
+    let dir = std::env::temp_dir().join(format!("dtools-load-{}", std::process::id()));
+    let s = format!(
+        r#"
+receiver = "someone@example.com"
+concurrency = 3
+
+[[subscriptions]]
+name = "B"
+cache = {:?}
+url = "{}stall"
+timeout = 1
+retries = 0
+stale_hours = 0
+
+[[subscriptions]]
+name = "A"
+cache = {:?}
+url = "{}ok"
+ttl = 0
+
+[[subscriptions]]
+name = "C"
+cache = {:?}
+url = "{}flaky"
+ttl = 0
+retries = 1
+"#,
+        dir.join("b.txt"),
+        url,
+        dir.join("a.txt"),
+        url,
+        dir.join("c.txt"),
+        url
+    );
+    let config: Config = toml::from_str(&s).unwrap();
+    let client = config::client_builder().build().unwrap();
+
+    let start = Instant::now();
+    let loaded = load_all(&config, &client, true).await;
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert_eq!(loaded.len(), 3);
+    let e = loaded[0].as_ref().unwrap_err();
+    assert!(format!("{:?}", e).contains("timed out"));
+    let (_, airport, stale) = loaded[1].as_ref().unwrap();
+    assert_eq!(airport.name, "A");
+    assert_eq!(airport.nodes.len(), 1);
+    assert!(stale.is_none());
+    // 失败一次后等待 1 秒重试成功
+    let (_, airport, _) = loaded[2].as_ref().unwrap();
+    assert_eq!(airport.name, "C");
+    assert!(start.elapsed() >= Duration::from_secs(1));
+
+    tokio::fs::remove_dir_all(&dir).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_run_counts() {
+    use hyper::{Body, Response};
+    use tokio::net::TcpListener;
+
+    let closed_port = {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap().port()
+    };
+    let open = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let open_port = open.local_addr().unwrap().port();
+    tokio::spawn(async move { while open.accept().await.is_ok() {} });
+
+    // A 有一个不可达的节点，B 的节点和 A 重复
+    let url = config::test_server(move |req| async move {
+        let body = match req.uri().path() {
+            "/a" => format!(
+                "trojan://p@127.0.0.1:{}#a1\ntrojan://p@127.0.0.1:{}#a2",
+                open_port, closed_port
+            ),
+            _ => format!("trojan://p@127.0.0.1:{}#b1", open_port),
+        };
+        Response::new(Body::from(body))
+    });
+    let dir = std::env::temp_dir().join(format!("dtools-run-{}", std::process::id()));
+    let s = format!(
+        r#"
+receiver = "someone@example.com"
+dedup = {{}}
+probe = {{}}
+
+[[subscriptions]]
+name = "A"
+cache = {:?}
+url = "{}a"
+
+[[subscriptions]]
+name = "B"
+cache = {:?}
+url = "{}b"
+"#,
+        dir.join("a.txt"),
+        url,
+        dir.join("b.txt"),
+        url
+    );
+    let config: Config = toml::from_str(&s).unwrap();
+    // --diff 不写缓存也不保存快照
+    run(&config, Mode::DIFF).await.unwrap();
+    assert!(!config.subscriptions[0].nodes_path().exists());
+    assert!(!dir.join("a.txt").exists());
+
+    let summary = run(&config, Mode::SERVE).await.unwrap();
+    let renamed = |name: &str| summary.airports[name].as_ref().unwrap().renamed;
+    assert_eq!(renamed("A"), 1);
+    assert_eq!(renamed("B"), 0);
+    assert_eq!(summary.airports["B"].as_ref().unwrap().duplicated, 1);
+    assert_eq!(summary.probe.unwrap().unreachable.len(), 1);
+    // serve 不保存节点快照
+    assert!(!config.subscriptions[0].nodes_path().exists());
+    assert!(dir.join("a.txt").exists());
+
+    run(&config, Mode::RENAME).await.unwrap();
+    assert!(config.subscriptions[0].nodes_path().exists());
+
+    tokio::fs::remove_dir_all(&dir).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_fallback_on_empty() {
+    use hyper::{Body, Response};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    // 第一次返回节点，之后返回 200 和空内容、HTML 错误页
+    let count = Arc::new(AtomicUsize::new(0));
+    let url = config::test_server(move |_| {
+        let count = count.clone();
+        async move {
+            let body = match count.fetch_add(1, Ordering::SeqCst) {
+                0 => "trojan://p@1.2.3.4:443#a",
+                1 => "",
+                _ => "<html><a href=\"https://example.com\">error</a></html>",
+            };
+            Response::new(Body::from(body))
+        }
+    });
+    let dir = std::env::temp_dir().join(format!("dtools-empty-{}", std::process::id()));
+    let sub = config::subscription(&format!(
+        "cache = {:?}\nurl = {:?}\nttl = 0\nretries = 0\npassthrough = true",
+        dir.join("a.txt"),
+        url
+    ));
+    let client = config::client_builder().build().unwrap();
+
+    let (_, airport, stale) = load(&sub, &client, true).await.unwrap();
+    assert_eq!(airport.nodes.len(), 1);
+    assert!(stale.is_none());
+
+    // 空内容和 HTML 都不覆盖缓存，退回到上次的缓存
+    for _ in 0..2 {
+        let (_, airport, stale) = load(&sub, &client, true).await.unwrap();
+        assert_eq!(airport.nodes.len(), 1);
+        assert_eq!(airport.passthrough_count(), 0);
+        assert!(stale.is_some());
+    }
+    assert_eq!(
+        tokio::fs::read_to_string(dir.join("a.txt")).await.unwrap(),
+        "trojan://p@1.2.3.4:443#a"
+    );
+
+    tokio::fs::remove_dir_all(&dir).await.unwrap();
+}
@@ -0,0 +1,105 @@
+//! 输出文件的生成
+use super::{
+    clash,
+    config::{Output, OutputFormat},
+    filter::Matcher,
+    parse::{Airport, Node},
+    region::Region,
+    singbox, surge, OutputReport,
+};
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use tokio::{
+    fs,
+    io::{AsyncWriteExt, BufWriter},
+};
+
+/// 按输出的配置挑选机场和节点
+fn select(output: &Output, airports: &[Airport]) -> Result<Vec<Airport>> {
+    let include = Matcher::new(&output.include)?;
+    let exclude = Matcher::new(&output.exclude)?;
+    let in_regions = |region: Option<&Region>| {
+        output.regions.is_empty()
+            || region.is_some_and(|region| {
+                output
+                    .regions
+                    .iter()
+                    .any(|code| code.eq_ignore_ascii_case(&region.code))
+            })
+    };
+
+    let selected = airports
+        .iter()
+        .filter(|airport| {
+            output.subscriptions.is_empty() || output.subscriptions.contains(&airport.name)
+        })
+        .map(|airport| {
+            let mut airport = airport.clone();
+            airport.filter(&include, true);
+            airport.filter(&exclude, false);
+            airport.retain(|_, region| in_regions(region));
+            airport
+        })
+        .collect();
+    Ok(selected)
+}
+
+fn uri_list(airports: &[Airport]) -> String {
+    airports
+        .iter()
+        .flat_map(|airport| airport.nodes.iter())
+        .map(|node| node.to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// 生成并写入一个输出
+pub async fn generate(output: &Output, airports: &[Airport]) -> Result<OutputReport> {
+    let airports = select(output, airports)?;
+    let selected: usize = airports.iter().map(|airport| airport.nodes.len()).sum();
+    let mut skipped = vec![];
+
+    let content = match &output.format {
+        OutputFormat::Base64 => base64::encode(uri_list(&airports)),
+        OutputFormat::Plain => uri_list(&airports),
+        OutputFormat::Clash(options) => {
+            let template = match &options.template {
+                Some(path) => Some(fs::read_to_string(path).await?),
+                None => None,
+            };
+            clash::render(&airports, template.as_deref(), options, &mut skipped)?
+        }
+        OutputFormat::SingBox(options) => {
+            let template = fs::read_to_string(&options.template).await?;
+            singbox::render(&airports, &template, options, &mut skipped)?
+        }
+        OutputFormat::Surge => surge::render(&airports, surge::surge, &mut skipped),
+        OutputFormat::QuantumultX => surge::render(&airports, surge::quantumult_x, &mut skipped),
+        OutputFormat::Loon => surge::render(&airports, surge::loon, &mut skipped),
+    };
+    write(&output.path, content).await?;
+
+    Ok(OutputReport {
+        nodes: selected - skipped.len(),
+        skipped,
+    })
+}
+
+/// 记录无法转换为目标格式的节点
+pub fn skip(skipped: &mut Vec<String>, node: &Node, e: &anyhow::Error) {
+    let name = node.name().unwrap_or_else(|_| node.to_string());
+    warn!("节点 {} 无法转换，跳过：{:?}", name, e);
+    skipped.push(format!("{}：{}", name, e));
+}
+
+/// 先写入同目录下的临时文件再改名，`serve` 不会读到写了一半的文件
+async fn write(path: &Path, content: String) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    let mut output_file = BufWriter::new(fs::File::create(&tmp).await?);
+    output_file.write_all(content.as_bytes()).await?;
+    output_file.flush().await?;
+    fs::rename(&tmp, path).await?;
+    Ok(())
+}
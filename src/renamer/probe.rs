@@ -0,0 +1,234 @@
+//! 探测节点是否可达：TCP 连接，可选 TLS 握手
+use super::{
+    config::{Probe, ProbeAction},
+    parse::{Airport, Node},
+};
+use anyhow::Result;
+use futures_util::stream::{self, StreamExt};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{net::TcpStream, time};
+use tokio_rustls::{
+    rustls::{self, ClientConfig},
+    webpki::DNSNameRef,
+    TlsConnector,
+};
+
+/// 探测的目标
+#[derive(Debug, PartialEq, Eq)]
+struct Target {
+    host: String,
+    port: u16,
+    /// 需要 TLS 握手时的 SNI
+    tls: Option<String>,
+}
+
+/// 单个节点的探测结果
+#[derive(Debug)]
+enum Outcome {
+    Reachable(Duration),
+    Unreachable(String),
+    /// 基于 UDP 的协议和原样保留的节点无法用 TCP 探测
+    Skipped,
+}
+
+/// 探测的结果
+#[derive(Debug, Default)]
+pub struct ProbeReport {
+    pub reachable: usize,
+    pub skipped: usize,
+    /// 不可达的节点及原因
+    pub unreachable: Vec<String>,
+}
+
+fn target(node: &Node) -> Option<Target> {
+    let (host, port) = node.address().ok()?;
+    let tls = match node {
+        Node::VMess { inner } => {
+            let field = |key: &str| inner.get(key).and_then(|v| v.as_str()).map(str::to_string);
+            if field("tls").as_deref() == Some("tls") {
+                Some(field("sni").or_else(|| field("host")).unwrap_or_default())
+            } else {
+                None
+            }
+        }
+        Node::Trojan { inner } => Some(
+            inner
+                .param("sni")
+                .or_else(|| inner.param("peer"))
+                .unwrap_or_default(),
+        ),
+        Node::Vless { inner } => match inner.param("security").as_deref() {
+            Some("tls") | Some("reality") => Some(inner.param("sni").unwrap_or_default()),
+            _ => None,
+        },
+        Node::Ssr { .. } | Node::Shadowsocks { .. } => None,
+        Node::Hysteria2 { .. } | Node::Tuic { .. } | Node::Opaque { .. } => return None,
+    };
+    let tls = tls.map(|sni| if sni.is_empty() { host.clone() } else { sni });
+    Some(Target { host, port, tls })
+}
+
+/// 只测握手的时间，不校验证书：很多节点使用自签名证书
+struct NoVerifier;
+impl rustls::ServerCertVerifier for NoVerifier {
+    fn verify_server_cert(
+        &self,
+        _roots: &rustls::RootCertStore,
+        _presented_certs: &[rustls::Certificate],
+        _dns_name: DNSNameRef,
+        _ocsp_response: &[u8],
+    ) -> Result<rustls::ServerCertVerified, rustls::TLSError> {
+        Ok(rustls::ServerCertVerified::assertion())
+    }
+}
+
+fn connector() -> TlsConnector {
+    let mut config = ClientConfig::new();
+    config
+        .dangerous()
+        .set_certificate_verifier(Arc::new(NoVerifier));
+    TlsConnector::from(Arc::new(config))
+}
+
+async fn connect(target: &Target, connector: Option<&TlsConnector>) -> Result<Duration> {
+    let start = Instant::now();
+    let stream = TcpStream::connect((target.host.as_str(), target.port)).await?;
+    if let (Some(sni), Some(connector)) = (&target.tls, connector) {
+        // IP 不能作为 SNI，随便给一个域名，反正不校验证书
+        let name = DNSNameRef::try_from_ascii_str(sni)
+            .or_else(|_| DNSNameRef::try_from_ascii_str("localhost"))
+            .map_err(|e| anyhow!("invalid sni {:?}: {:?}", sni, e))?;
+        connector.connect(name, stream).await?;
+    }
+    Ok(start.elapsed())
+}
+
+async fn probe_node(node: &Node, options: &Probe, connector: Option<&TlsConnector>) -> Outcome {
+    let target = match target(node) {
+        Some(target) => target,
+        None => return Outcome::Skipped,
+    };
+    let timeout = Duration::from_millis(options.timeout);
+    match time::timeout(timeout, connect(&target, connector)).await {
+        Ok(Ok(latency)) => Outcome::Reachable(latency),
+        Ok(Err(e)) => Outcome::Unreachable(e.to_string()),
+        Err(_) => Outcome::Unreachable(format!("timed out after {} ms", options.timeout)),
+    }
+}
+
+/// 探测所有节点，按配置去掉或标记不可达的节点，并可以按延迟排序
+pub async fn probe(airports: &mut [Airport], options: &Probe) -> ProbeReport {
+    let connector = if options.tls { Some(connector()) } else { None };
+    let connector = connector.as_ref();
+    let probes: Vec<_> = airports
+        .iter()
+        .flat_map(|airport| airport.nodes.iter())
+        .map(|node| probe_node(node, options, connector))
+        .collect();
+    let mut outcomes = stream::iter(probes)
+        .buffered(options.concurrency.max(1))
+        .collect::<Vec<_>>()
+        .await
+        .into_iter();
+
+    let mut report = ProbeReport::default();
+    for airport in airports.iter_mut() {
+        let mut probed = vec![];
+        for (mut node, region) in airport.take_nodes() {
+            let outcome = outcomes.next().unwrap_or(Outcome::Skipped);
+            let name = node.name().unwrap_or_else(|_| node.to_string());
+            // 排序用的延迟，不可达的排在最后
+            let latency = match &outcome {
+                Outcome::Reachable(latency) => {
+                    debug!("{} is reachable in {:?}", name, latency);
+                    report.reachable += 1;
+                    *latency
+                }
+                Outcome::Skipped => {
+                    report.skipped += 1;
+                    Duration::MAX - Duration::from_secs(1)
+                }
+                Outcome::Unreachable(reason) => {
+                    debug!("{} is unreachable: {}", name, reason);
+                    report
+                        .unreachable
+                        .push(format!("{}（{}）：{}", name, airport.name, reason));
+                    if options.action == ProbeAction::Drop {
+                        continue;
+                    }
+                    if let Err(e) = node.set_name(format!("{} {}", options.tag, name)) {
+                        warn!("无法标记节点 {}：{:?}", name, e);
+                    }
+                    Duration::MAX
+                }
+            };
+            probed.push((latency, node, region));
+        }
+        if options.sort {
+            // 稳定排序，延迟相同（如都无法探测）的保持原顺序
+            probed.sort_by_key(|(latency, ..)| *latency);
+        }
+        for (_, node, region) in probed {
+            airport.push(node, region);
+        }
+    }
+    report
+}
+
+#[tokio::test]
+async fn test_probe() {
+    use super::config::Format;
+    use tokio::net::TcpListener;
+
+    let open = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let open_port = open.local_addr().unwrap().port();
+    let closed_port = {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap().port()
+    };
+    // 接受连接后立即关闭，TLS 握手会失败
+    tokio::spawn(async move {
+        loop {
+            if let Ok((stream, _)) = open.accept().await {
+                drop(stream);
+            }
+        }
+    });
+
+    let links = [
+        format!("ss://YWVzLTEyOC1nY206cA@127.0.0.1:{}#closed", closed_port),
+        format!("ss://YWVzLTEyOC1nY206cA@127.0.0.1:{}#open", open_port),
+        format!("hysteria2://p@127.0.0.1:{}#quic", closed_port),
+        format!("trojan://p@127.0.0.1:{}#tls", open_port),
+    ];
+    let airport = Airport::new("A", links.join("\n"), Format::Auto, false).unwrap();
+    let names = |airports: &[Airport]| -> Vec<String> {
+        airports[0]
+            .nodes
+            .iter()
+            .map(|n| n.name().unwrap())
+            .collect()
+    };
+    let options = |s: &str| -> Probe { toml::from_str(s).unwrap() };
+
+    let mut airports = vec![airport.clone()];
+    let report = probe(&mut airports, &options("")).await;
+    assert_eq!(names(&airports), vec!["open", "quic", "tls"]);
+    assert_eq!((report.reachable, report.skipped), (2, 1));
+    assert_eq!(report.unreachable.len(), 1);
+
+    let mut airports = vec![airport];
+    let report = probe(
+        &mut airports,
+        &options("tls = true\naction = \"tag\"\nsort = true"),
+    )
+    .await;
+    assert_eq!(
+        names(&airports),
+        vec!["open", "quic", "❌ closed", "❌ tls"]
+    );
+    assert_eq!(report.unreachable.len(), 2);
+}
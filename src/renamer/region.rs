@@ -0,0 +1,136 @@
+//! 根据节点名判断地区
+use std::fmt;
+
+/// 内置的地区表：ISO 代码和关键词
+#[rustfmt::skip]
+const BUILTIN: &[(&str, &[&str])] = &[
+    ("HK", &["香港", "港", "Hong Kong", "HongKong", "HKG"]),
+    ("TW", &["台湾", "台灣", "臺灣", "台北", "Taiwan", "TWN"]),
+    ("MO", &["澳门", "澳門", "Macao", "Macau"]),
+    ("JP", &["日本", "东京", "東京", "大阪", "Japan", "Tokyo", "Osaka", "JPN"]),
+    ("KR", &["韩国", "韓國", "首尔", "Korea", "Seoul", "KOR"]),
+    ("SG", &["新加坡", "狮城", "Singapore", "SGP"]),
+    ("US", &["美国", "美國", "洛杉矶", "硅谷", "西雅图", "纽约", "United States", "USA",
+             "America", "Los Angeles", "San Jose", "Seattle"]),
+    ("CA", &["加拿大", "Canada"]),
+    ("GB", &["英国", "英國", "伦敦", "United Kingdom", "Britain", "London", "UK"]),
+    ("DE", &["德国", "德國", "法兰克福", "Germany", "Frankfurt", "DEU"]),
+    ("FR", &["法国", "法國", "巴黎", "France", "Paris", "FRA"]),
+    ("NL", &["荷兰", "荷蘭", "阿姆斯特丹", "Netherlands", "Amsterdam", "NLD"]),
+    ("RU", &["俄罗斯", "俄羅斯", "莫斯科", "Russia", "Moscow", "RUS"]),
+    ("TR", &["土耳其", "Turkey", "Türkiye", "TUR"]),
+    ("IN", &["印度", "India", "IND"]),
+    ("AU", &["澳大利亚", "澳洲", "悉尼", "Australia", "Sydney", "AUS"]),
+    ("AR", &["阿根廷", "Argentina", "ARG"]),
+    ("BR", &["巴西", "Brazil", "BRA"]),
+    ("MY", &["马来西亚", "Malaysia", "MYS"]),
+    ("TH", &["泰国", "泰國", "Thailand", "THA"]),
+    ("VN", &["越南", "Vietnam", "VNM"]),
+    ("PH", &["菲律宾", "Philippines", "PHL"]),
+    ("ID", &["印尼", "印度尼西亚", "Indonesia", "IDN"]),
+];
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Region {
+    /// ISO 3166-1 两位代码，如 `HK`
+    pub code: String,
+    #[serde(default)]
+    pub keywords: Vec<String>,
+}
+impl Region {
+    /// 由 ISO 代码生成的旗帜 emoji
+    pub fn flag(&self) -> String {
+        let code = match self.code.as_str() {
+            "UK" => "GB",
+            code => code,
+        };
+        code.chars()
+            .filter(char::is_ascii_alphabetic)
+            .filter_map(|c| {
+                std::char::from_u32(0x1F1E6 + (c.to_ascii_uppercase() as u32 - 'A' as u32))
+            })
+            .collect()
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        name.contains(&self.flag())
+            || contains_code(name, &self.code)
+            || self.keywords.iter().any(|kw| {
+                if kw.is_ascii() {
+                    // 英文关键词不区分大小写，并且不能是别的单词的一部分
+                    contains_code(&name.to_ascii_lowercase(), &kw.to_ascii_lowercase())
+                } else {
+                    name.contains(kw.as_str())
+                }
+            })
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.flag(), self.code)
+    }
+}
+
+/// `needle` 在 `haystack` 中出现，并且两侧不是英文字母
+fn contains_code(haystack: &str, needle: &str) -> bool {
+    haystack.match_indices(needle).any(|(i, _)| {
+        let before = haystack[..i].chars().next_back();
+        let after = haystack[i + needle.len()..].chars().next();
+        !matches!(before, Some(c) if c.is_ascii_alphabetic())
+            && !matches!(after, Some(c) if c.is_ascii_alphabetic())
+    })
+}
+
+/// 地区分类器，按顺序匹配，先匹配到的优先
+#[derive(Debug, Clone)]
+pub struct Regions {
+    regions: Vec<Region>,
+}
+impl Default for Regions {
+    fn default() -> Self {
+        Self::new(&[])
+    }
+}
+impl Regions {
+    /// 自定义的地区排在内置表前面，优先匹配
+    pub fn new(custom: &[Region]) -> Self {
+        let builtin = BUILTIN.iter().map(|(code, keywords)| Region {
+            code: code.to_string(),
+            keywords: keywords.iter().map(|kw| kw.to_string()).collect(),
+        });
+        let regions = custom.iter().cloned().chain(builtin).collect();
+        Self { regions }
+    }
+
+    pub fn detect(&self, name: &str) -> Option<&Region> {
+        self.regions.iter().find(|region| region.matches(name))
+    }
+}
+
+#[test]
+fn test_detect() {
+    let regions = Regions::default();
+    let detect = |name: &str| regions.detect(name).map(|r| r.code.as_str());
+    assert_eq!(detect("香港 01"), Some("HK"));
+    assert_eq!(detect("HK|IEPL"), Some("HK"));
+    assert_eq!(detect("🇭🇰Hong Kong"), Some("HK"));
+    assert_eq!(detect("hong kong 02"), Some("HK"));
+    assert_eq!(detect("🇯🇵 东京 02"), Some("JP"));
+    assert_eq!(detect("US-Seattle"), Some("US"));
+    assert_eq!(detect("Thanks"), None);
+    assert_eq!(detect("官网"), None);
+
+    assert_eq!(regions.detect("日本").unwrap().to_string(), "🇯🇵 JP");
+}
+
+#[test]
+fn test_custom() {
+    let custom = vec![Region {
+        code: "HK".to_string(),
+        keywords: vec!["沪港".to_string()],
+    }];
+    let regions = Regions::new(&custom);
+    assert_eq!(regions.detect("沪港 IPLC").unwrap().code, "HK");
+    assert_eq!(regions.detect("日本").unwrap().code, "JP");
+}
@@ -0,0 +1,277 @@
+//! 通过 HTTP 提供输出文件，订阅地址为 `/{token}/{输出名}`
+use super::{
+    config::{Config, Output, OutputFormat, User},
+    userinfo,
+};
+use anyhow::Result;
+use hyper::{
+    header,
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server, StatusCode,
+};
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tokio::{fs, sync::Mutex};
+
+/// 比较所用的时间只和长度有关，避免通过响应时间逐字节猜出 token
+fn token_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+struct State {
+    config: Config,
+    /// 同一时间只重新生成一次
+    regenerating: Mutex<()>,
+}
+impl State {
+    /// 按路径找到用户和输出，token 错误或无权访问时为 `None`
+    fn route(&self, path: &str) -> Option<(&User, Output)> {
+        let serve = self.config.serve.as_ref()?;
+        let (token, name) = path.trim_start_matches('/').split_once('/')?;
+        // 比较所有用户，不在第一个匹配处提前返回
+        let user = serve.users.iter().fold(None, |found, user| {
+            if token_eq(&user.token, token) {
+                Some(user)
+            } else {
+                found
+            }
+        })?;
+        if !user.outputs.is_empty() && !user.outputs.iter().any(|output| output == name) {
+            return None;
+        }
+        let output = self
+            .config
+            .outputs()
+            .into_iter()
+            .find(|output| output.name == name)?;
+        Some((user, output))
+    }
+
+    /// 输出文件不存在或超过 ttl 时重新生成
+    async fn refresh(&self, output: &Output) -> Result<()> {
+        let ttl = Duration::from_secs(self.config.serve.as_ref().map_or(0, |serve| serve.ttl));
+        let fresh = || async {
+            let modified = fs::metadata(&output.path).await.and_then(|m| m.modified());
+            matches!(modified, Ok(t) if SystemTime::now().duration_since(t).unwrap_or_default() < ttl)
+        };
+        if fresh().await {
+            return Ok(());
+        }
+        let _guard = self.regenerating.lock().await;
+        // 等锁的时候可能已经被别的请求重新生成了
+        if fresh().await {
+            return Ok(());
+        }
+        info!("output {} is outdated, regenerating.", output.name);
+        let summary = super::run(&self.config, super::Mode::SERVE).await?;
+        for (name, result) in summary.outputs {
+            if let Err(e) = result {
+                warn!("输出 {} 失败：{:?}", name, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// 输出中各机场的流量信息之和
+    async fn userinfo(&self, output: &Output) -> Option<userinfo::UserInfo> {
+        let mut infos = vec![];
+        for sub in &self.config.subscriptions {
+            if !output.subscriptions.is_empty() && !output.subscriptions.contains(&sub.name) {
+                continue;
+            }
+            infos.extend(sub.cached_userinfo().await);
+        }
+        userinfo::aggregate(&infos)
+    }
+
+    async fn handle(&self, req: Request<Body>) -> Result<Response<Body>> {
+        let (user, output) = match self.route(req.uri().path()) {
+            Some(route) => route,
+            None => {
+                return Ok(Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .body(Body::empty())?)
+            }
+        };
+        debug!("{} requested output {}", user.name, output.name);
+        self.refresh(&output).await?;
+
+        let content = fs::read(&output.path).await?;
+        let content_type = match output.format {
+            OutputFormat::Clash(_) => "text/yaml; charset=utf-8",
+            OutputFormat::SingBox(_) => "application/json; charset=utf-8",
+            _ => "text/plain; charset=utf-8",
+        };
+        let mut response = Response::builder().header(header::CONTENT_TYPE, content_type);
+        if let Some(info) = self.userinfo(&output).await {
+            response = response.header(userinfo::HEADER, info.to_string());
+        }
+        Ok(response.body(Body::from(content))?)
+    }
+}
+
+pub async fn main(config: Config) -> Result<()> {
+    let serve = config
+        .serve
+        .as_ref()
+        .ok_or_else(|| anyhow!("serve is not configured"))?;
+    let addr: SocketAddr = serve.listen.parse()?;
+    let state = Arc::new(State {
+        config,
+        regenerating: Mutex::new(()),
+    });
+
+    let make_service = make_service_fn(move |_| {
+        let state = state.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let state = state.clone();
+                async move {
+                    let response = state.handle(req).await.unwrap_or_else(|e| {
+                        warn!("处理请求失败：{:?}", e);
+                        let mut response = Response::new(Body::empty());
+                        *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+                        response
+                    });
+                    Ok::<_, Infallible>(response)
+                }
+            }))
+        }
+    });
+    info!("serving on http://{}", addr);
+    Server::bind(&addr).serve(make_service).await?;
+    Ok(())
+}
+
+#[test]
+fn test_route() {
+    let s = r#"
+receiver = "someone@example.com"
+subscriptions = []
+
+[[outputs]]
+name = "clash"
+path = "clash.yaml"
+format = "clash"
+
+[[outputs]]
+name = "plain"
+path = "plain.txt"
+format = "plain"
+
+[serve]
+listen = "127.0.0.1:8080"
+
+[[serve.users]]
+name = "alice"
+token = "secret-a"
+
+[[serve.users]]
+name = "bob"
+token = "secret-b"
+outputs = ["plain"]
+"#;
+    let state = State {
+        config: toml::from_str(s).unwrap(),
+        regenerating: Mutex::new(()),
+    };
+    let route = |path: &str| {
+        state
+            .route(path)
+            .map(|(user, output)| (user.name.clone(), output.name))
+    };
+    assert_eq!(
+        route("/secret-a/clash"),
+        Some(("alice".into(), "clash".into()))
+    );
+    assert_eq!(
+        route("/secret-b/plain"),
+        Some(("bob".into(), "plain".into()))
+    );
+    assert_eq!(route("/secret-b/clash"), None);
+    assert_eq!(route("/wrong/plain"), None);
+    assert_eq!(route("/secret-a/missing"), None);
+    assert_eq!(route("/secret-a"), None);
+    assert!(token_eq("secret-a", "secret-a"));
+    assert!(!token_eq("secret-a", "secret-b"));
+    assert!(!token_eq("secret-a", "secret"));
+}
+
+#[tokio::test]
+async fn test_handle() {
+    use hyper::body;
+
+    let url = super::config::test_server(|_| async {
+        Response::builder()
+            .header(userinfo::HEADER, "upload=1; download=2; total=10")
+            .body(Body::from("trojan://p@1.2.3.4:443#a"))
+            .unwrap()
+    });
+    let dir = std::env::temp_dir().join(format!("dtools-serve-{}", std::process::id()));
+    let output = dir.join("plain.txt");
+    let state = |ttl: u64| {
+        let s = format!(
+            r#"
+receiver = "someone@example.com"
+
+[[subscriptions]]
+name = "A"
+cache = {:?}
+url = {:?}
+ttl = 0
+
+[[outputs]]
+name = "plain"
+path = {:?}
+format = "plain"
+
+[serve]
+listen = "127.0.0.1:0"
+ttl = {}
+
+[[serve.users]]
+name = "alice"
+token = "secret"
+"#,
+            dir.join("a.txt"),
+            url,
+            output,
+            ttl
+        );
+        State {
+            config: toml::from_str(&s).unwrap(),
+            regenerating: Mutex::new(()),
+        }
+    };
+    let get = |state: State| async move {
+        let req = Request::get("/secret/plain").body(Body::empty()).unwrap();
+        let response = state.handle(req).await.unwrap();
+        let info = response.headers().get(userinfo::HEADER).cloned();
+        let content = body::to_bytes(response.into_body()).await.unwrap();
+        (String::from_utf8(content.to_vec()).unwrap(), info)
+    };
+
+    // 输出不存在时生成，并带上机场的流量信息
+    fs::create_dir_all(&dir).await.unwrap();
+    let (content, info) = get(state(3600)).await;
+    assert_eq!(content, "trojan://p@1.2.3.4:443#a%20-%20A");
+    assert_eq!(
+        info.unwrap().to_str().unwrap(),
+        "upload=1; download=2; total=10"
+    );
+
+    // 未超过 ttl 时直接返回，超过时重新生成
+    fs::write(&output, "old").await.unwrap();
+    assert_eq!(get(state(3600)).await.0, "old");
+    assert_eq!(get(state(0)).await.0, "trojan://p@1.2.3.4:443#a%20-%20A");
+
+    fs::remove_dir_all(&dir).await.unwrap();
+}
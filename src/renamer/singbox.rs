@@ -0,0 +1,587 @@
+//! sing-box 配置文件的解析和生成
+use super::{
+    config::SingBoxOutput,
+    output,
+    parse::{decode_component, encode_component, json_str, Airport, Node, Uri},
+    view::View,
+};
+use anyhow::{anyhow, bail, Result};
+use serde_json::{json, Map, Value};
+
+/// 生成的主选择器和自动测速分组的 tag，模板中的路由应当指向它们
+const PROXY: &str = "proxy";
+const AUTO: &str = "auto";
+
+#[derive(Debug, Deserialize)]
+struct Document {
+    outbounds: Vec<Value>,
+}
+
+/// 把 sing-box 配置中的 `outbounds` 转换为节点，
+/// selector / direct 等非代理出站和不支持的协议会被跳过
+pub fn parse(content: &str) -> Result<Vec<Node>> {
+    let document: Document = serde_json::from_str(content)?;
+    let mut nodes = vec![];
+    for outbound in document.outbounds {
+        match View(&outbound).to_node() {
+            Ok(Some(node)) => nodes.push(node),
+            Ok(None) => {}
+            Err(e) => warn!("error parse sing-box outbound: {:?}. ignore.", e),
+        }
+    }
+    Ok(nodes)
+}
+
+/// sing-box outbound 的只读视图
+type Outbound<'a> = View<'a, Value>;
+impl<'a> Outbound<'a> {
+    /// `alpn` 既可以是字符串也可以是数组
+    fn alpn(&self) -> Option<String> {
+        match self.get("alpn")? {
+            Value::String(s) => Some(s.clone()),
+            Value::Array(values) => Some(
+                values
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .collect::<Vec<_>>()
+                    .join(","),
+            ),
+            _ => None,
+        }
+    }
+
+    fn to_node(&self) -> Result<Option<Node>> {
+        let kind = self.require("type")?;
+        let node = match kind {
+            "shadowsocks" => self.shadowsocks()?,
+            "vmess" => self.vmess()?,
+            "trojan" => Node::Trojan {
+                inner: self.uri("trojan", encode_component(self.require("password")?))?,
+            },
+            "vless" => Node::Vless {
+                inner: self.uri("vless", encode_component(self.require("uuid")?))?,
+            },
+            "hysteria2" => Node::Hysteria2 {
+                inner: self.uri(
+                    "hysteria2",
+                    encode_component(self.str("password").unwrap_or_default()),
+                )?,
+            },
+            "tuic" => Node::Tuic {
+                inner: self.uri(
+                    "tuic",
+                    format!(
+                        "{}:{}",
+                        encode_component(self.require("uuid")?),
+                        encode_component(self.str("password").unwrap_or_default())
+                    ),
+                )?,
+            },
+            "selector" | "urltest" | "direct" | "block" | "dns" => return Ok(None),
+            _ => return Err(anyhow!("sing-box: unsupported outbound type {}", kind)),
+        };
+        Ok(Some(node))
+    }
+
+    fn shadowsocks(&self) -> Result<Node> {
+        let plugin = self.str("plugin").map(|plugin| {
+            // sing-box 用 obfs-local，参数与 SIP002 一致
+            match self.str("plugin_opts") {
+                Some(opts) if !opts.is_empty() => format!("{};{}", plugin, opts),
+                _ => plugin.to_string(),
+            }
+        });
+        Ok(Node::Shadowsocks {
+            method: self.require("method")?.to_string(),
+            password: self.require("password")?.to_string(),
+            server: self.require("server")?.to_string(),
+            port: self.port("server_port")?,
+            plugin,
+            query: vec![],
+            tag: self.require("tag")?.to_string(),
+        })
+    }
+
+    fn vmess(&self) -> Result<Node> {
+        let tls = self.opts("tls");
+        let transport = self.opts("transport");
+        let network = transport.str("type").unwrap_or("tcp");
+        let (host, path) = match network {
+            "ws" => (transport.opts("headers").str("Host"), transport.str("path")),
+            "http" => (
+                transport
+                    .get("host")
+                    .and_then(Value::as_array)
+                    .and_then(|hosts| hosts.first())
+                    .and_then(Value::as_str),
+                transport.str("path"),
+            ),
+            "grpc" => (None, transport.str("service_name")),
+            _ => (None, None),
+        };
+        let inner = serde_json::json!({
+            "v": "2",
+            "ps": self.require("tag")?,
+            "add": self.require("server")?,
+            "port": self.port("server_port")?.to_string(),
+            "id": self.require("uuid")?,
+            "aid": self.get("alter_id").and_then(Value::as_u64).unwrap_or(0).to_string(),
+            "scy": self.str("security").unwrap_or("auto"),
+            "net": if network == "http" { "h2" } else { network },
+            "type": "none",
+            "host": host.unwrap_or_default(),
+            "path": path.unwrap_or_default(),
+            "tls": if tls.bool("enabled") { "tls" } else { "" },
+            "sni": tls.str("server_name").unwrap_or_default(),
+        });
+        Ok(Node::VMess { inner })
+    }
+
+    /// trojan / vless / hysteria2 / tuic 共用的链接格式
+    fn uri(&self, scheme: &str, userinfo: String) -> Result<Uri> {
+        let mut uri = Uri::new(
+            scheme,
+            userinfo,
+            self.require("server")?,
+            self.port("server_port")?,
+            self.require("tag")?,
+        );
+
+        if let Some(flow) = self.str("flow") {
+            uri.push_param("flow", flow);
+        }
+
+        // QUIC 相关
+        let obfs = self.opts("obfs");
+        if let Some(kind) = obfs.str("type") {
+            uri.push_param("obfs", kind);
+            if let Some(password) = obfs.str("password") {
+                uri.push_param("obfs-password", password);
+            }
+        }
+        if let Some(cc) = self.str("congestion_control") {
+            uri.push_param("congestion_control", cc);
+        }
+        if let Some(mode) = self.str("udp_relay_mode") {
+            uri.push_param("udp_relay_mode", mode);
+        }
+
+        // TLS
+        let tls = self.opts("tls");
+        let reality = tls.opts("reality");
+        if reality.bool("enabled") {
+            uri.push_param("security", "reality");
+            if let Some(pbk) = reality.str("public_key") {
+                uri.push_param("pbk", pbk);
+            }
+            if let Some(sid) = reality.str("short_id") {
+                uri.push_param("sid", sid);
+            }
+        } else if tls.bool("enabled") && matches!(scheme, "trojan" | "vless") {
+            uri.push_param("security", "tls");
+        }
+        if let Some(sni) = tls.str("server_name") {
+            uri.push_param("sni", sni);
+        }
+        if let Some(fp) = tls.opts("utls").str("fingerprint") {
+            uri.push_param("fp", fp);
+        }
+        if let Some(alpn) = tls.alpn() {
+            uri.push_param("alpn", &alpn);
+        }
+        if tls.bool("insecure") {
+            uri.push_param("insecure", "1");
+        }
+
+        // 传输层
+        let transport = self.opts("transport");
+        match transport.str("type") {
+            Some("ws") => {
+                uri.push_param("type", "ws");
+                if let Some(host) = transport.opts("headers").str("Host") {
+                    uri.push_param("host", host);
+                }
+                if let Some(path) = transport.str("path") {
+                    uri.push_param("path", path);
+                }
+            }
+            Some("grpc") => {
+                uri.push_param("type", "grpc");
+                if let Some(name) = transport.str("service_name") {
+                    uri.push_param("serviceName", name);
+                }
+            }
+            Some(other) => uri.push_param("type", other),
+            None => {}
+        }
+
+        Ok(uri)
+    }
+}
+
+/// 生成 sing-box 配置：节点和分组会插入到模板 `outbounds` 的最前面。
+/// 无法转换的节点记录在 `skipped` 中。
+pub fn render(
+    airports: &[Airport],
+    template: &str,
+    options: &SingBoxOutput,
+    skipped: &mut Vec<String>,
+) -> Result<String> {
+    let mut outbounds = vec![];
+    let mut groups = vec![];
+    let mut all = vec![];
+    let mut airport_tags = vec![];
+    let mut region_groups: Vec<(String, Vec<String>)> = vec![];
+
+    for airport in airports {
+        let mut tags = vec![];
+        for (i, node) in airport.nodes.iter().enumerate() {
+            match to_outbound(node) {
+                Ok(outbound) => {
+                    let tag = node.name()?;
+                    if let Some(region) = airport.region(i) {
+                        let region = region.to_string();
+                        match region_groups.iter_mut().find(|(r, _)| *r == region) {
+                            Some((_, tags)) => tags.push(tag.clone()),
+                            None => region_groups.push((region, vec![tag.clone()])),
+                        }
+                    }
+                    tags.push(tag.clone());
+                    all.push(tag);
+                    outbounds.push(outbound);
+                }
+                Err(e) => output::skip(skipped, node, &e),
+            }
+        }
+        if tags.is_empty() {
+            continue;
+        }
+        let auto = format!("{} - {}", airport.name, AUTO);
+        let mut selector = vec![auto.clone()];
+        selector.extend(tags.iter().cloned());
+        groups.push(group(&airport.name, "selector", selector, None));
+        groups.push(group(&auto, "urltest", tags, Some(options)));
+        airport_tags.push(airport.name.clone());
+    }
+    if all.is_empty() {
+        bail!("no node can be converted to sing-box outbound");
+    }
+
+    let mut main = vec![AUTO.to_string()];
+    main.extend(airport_tags);
+    main.extend(region_groups.iter().map(|(region, _)| region.clone()));
+    let mut result = vec![
+        group(PROXY, "selector", main, None),
+        group(AUTO, "urltest", all, Some(options)),
+    ];
+    result.extend(groups);
+    for (region, tags) in region_groups {
+        result.push(group(&region, "urltest", tags, Some(options)));
+    }
+    result.extend(outbounds);
+
+    let mut document: Map<String, Value> = serde_json::from_str(template)?;
+    if let Some(Value::Array(extra)) = document.remove("outbounds") {
+        result.extend(extra);
+    }
+    document.insert("outbounds".to_string(), Value::Array(result));
+
+    Ok(serde_json::to_string_pretty(&document)?)
+}
+
+fn group(tag: &str, kind: &str, outbounds: Vec<String>, test: Option<&SingBoxOutput>) -> Value {
+    let mut group = json!({
+        "type": kind,
+        "tag": tag,
+        "outbounds": outbounds,
+    });
+    if let Some(options) = test {
+        group["url"] = json!(options.test_url);
+        group["interval"] = json!(format!("{}s", options.interval));
+    }
+    group
+}
+
+/// ws / grpc / http 传输层
+fn transport(network: &str, host: Option<String>, path: Option<String>) -> Option<Value> {
+    let transport = match network {
+        "ws" => {
+            let mut transport = json!({ "type": "ws" });
+            if let Some(path) = path {
+                transport["path"] = json!(path);
+            }
+            if let Some(host) = host {
+                transport["headers"] = json!({ "Host": host });
+            }
+            transport
+        }
+        "h2" | "http" => {
+            let mut transport = json!({ "type": "http" });
+            if let Some(host) = host {
+                transport["host"] = json!([host]);
+            }
+            if let Some(path) = path {
+                transport["path"] = json!(path);
+            }
+            transport
+        }
+        "grpc" => json!({ "type": "grpc", "service_name": path.unwrap_or_default() }),
+        _ => return None,
+    };
+    Some(transport)
+}
+
+/// 节点转换为 sing-box 的 outbound
+pub fn to_outbound(node: &Node) -> Result<Value> {
+    let tag = node.name()?;
+    let outbound = match node {
+        Node::VMess { inner } => {
+            let port: u16 = json_str(inner, "port")
+                .ok_or_else(|| anyhow!("vmess: port not found"))?
+                .parse()?;
+            let mut outbound = json!({
+                "type": "vmess",
+                "tag": tag,
+                "server": json_str(inner, "add").ok_or_else(|| anyhow!("vmess: add not found"))?,
+                "server_port": port,
+                "uuid": json_str(inner, "id").ok_or_else(|| anyhow!("vmess: id not found"))?,
+                "security": json_str(inner, "scy").unwrap_or_else(|| "auto".to_string()),
+                "alter_id": json_str(inner, "aid").and_then(|aid| aid.parse::<u32>().ok()).unwrap_or(0),
+            });
+            if json_str(inner, "tls").as_deref() == Some("tls") {
+                let mut tls = json!({ "enabled": true });
+                if let Some(sni) = json_str(inner, "sni") {
+                    tls["server_name"] = json!(sni);
+                }
+                outbound["tls"] = tls;
+            }
+            let network = json_str(inner, "net").unwrap_or_default();
+            if let Some(t) = transport(&network, json_str(inner, "host"), json_str(inner, "path")) {
+                outbound["transport"] = t;
+            }
+            outbound
+        }
+        Node::Ssr { .. } => bail!("ssr is not supported by sing-box"),
+        Node::Shadowsocks {
+            method,
+            password,
+            server,
+            port,
+            plugin,
+            ..
+        } => {
+            let mut outbound = json!({
+                "type": "shadowsocks",
+                "tag": tag,
+                "server": server,
+                "server_port": port,
+                "method": method,
+                "password": password,
+            });
+            if let Some(plugin) = plugin {
+                let (name, opts) = plugin.split_once(';').unwrap_or((plugin, ""));
+                let name = match name {
+                    "simple-obfs" => "obfs-local",
+                    name => name,
+                };
+                if !matches!(name, "obfs-local" | "v2ray-plugin") {
+                    bail!("ss: plugin {} is not supported by sing-box", name);
+                }
+                outbound["plugin"] = json!(name);
+                outbound["plugin_opts"] = json!(opts);
+            }
+            outbound
+        }
+        Node::Trojan { inner } => {
+            let mut outbound = uri_outbound("trojan", &tag, inner);
+            outbound["password"] = json!(decode_component(&inner.userinfo)?);
+            let mut tls = uri_tls(inner);
+            tls["enabled"] = json!(inner.param("security").as_deref() != Some("none"));
+            outbound["tls"] = tls;
+            outbound
+        }
+        Node::Vless { inner } => {
+            let mut outbound = uri_outbound("vless", &tag, inner);
+            outbound["uuid"] = json!(decode_component(&inner.userinfo)?);
+            if let Some(flow) = inner.param("flow").filter(|flow| !flow.is_empty()) {
+                outbound["flow"] = json!(flow);
+            }
+            match inner.param("security").as_deref() {
+                Some("tls") => {
+                    let mut tls = uri_tls(inner);
+                    tls["enabled"] = json!(true);
+                    outbound["tls"] = tls;
+                }
+                Some("reality") => {
+                    let mut tls = uri_tls(inner);
+                    tls["enabled"] = json!(true);
+                    tls["reality"] = json!({
+                        "enabled": true,
+                        "public_key": inner.param("pbk").unwrap_or_default(),
+                        "short_id": inner.param("sid").unwrap_or_default(),
+                    });
+                    outbound["tls"] = tls;
+                }
+                _ => {}
+            }
+            outbound
+        }
+        Node::Hysteria2 { inner } => {
+            let mut outbound = uri_outbound("hysteria2", &tag, inner);
+            outbound["password"] = json!(decode_component(&inner.userinfo)?);
+            if let Some(obfs) = inner.param("obfs") {
+                outbound["obfs"] = json!({
+                    "type": obfs,
+                    "password": inner.param("obfs-password").unwrap_or_default(),
+                });
+            }
+            let mut tls = uri_tls(inner);
+            tls["enabled"] = json!(true);
+            outbound["tls"] = tls;
+            outbound
+        }
+        Node::Tuic { inner } => {
+            let userinfo = decode_component(&inner.userinfo)?;
+            let (uuid, password) = userinfo.split_once(':').unwrap_or((&userinfo, ""));
+            let mut outbound = uri_outbound("tuic", &tag, inner);
+            outbound["uuid"] = json!(uuid);
+            outbound["password"] = json!(password);
+            if let Some(cc) = inner.param("congestion_control") {
+                outbound["congestion_control"] = json!(cc);
+            }
+            if let Some(mode) = inner.param("udp_relay_mode") {
+                outbound["udp_relay_mode"] = json!(mode);
+            }
+            let mut tls = uri_tls(inner);
+            tls["enabled"] = json!(true);
+            outbound["tls"] = tls;
+            outbound
+        }
+        Node::Opaque { .. } => bail!("opaque node cannot be converted"),
+    };
+    Ok(outbound)
+}
+
+/// 链接类节点共有的字段和传输层
+fn uri_outbound(kind: &str, tag: &str, uri: &Uri) -> Value {
+    let mut outbound = json!({
+        "type": kind,
+        "tag": tag,
+        "server": uri.server,
+        "server_port": uri.port,
+    });
+    if let Some(network) = uri.param("type") {
+        let path = match network.as_str() {
+            "grpc" => uri.param("serviceName"),
+            _ => uri.param("path"),
+        };
+        if let Some(t) = transport(&network, uri.param("host"), path) {
+            outbound["transport"] = t;
+        }
+    }
+    outbound
+}
+
+/// 链接中的 TLS 参数，`enabled` 由调用方决定
+fn uri_tls(uri: &Uri) -> Value {
+    let mut tls = json!({});
+    if let Some(sni) = uri.param("sni").or_else(|| uri.param("peer")) {
+        tls["server_name"] = json!(sni);
+    }
+    if let Some(alpn) = uri.param("alpn").filter(|alpn| !alpn.is_empty()) {
+        tls["alpn"] = json!(alpn.split(',').collect::<Vec<_>>());
+    }
+    if let Some(fp) = uri.param("fp") {
+        tls["utls"] = json!({ "enabled": true, "fingerprint": fp });
+    }
+    if uri.insecure() {
+        tls["insecure"] = json!(true);
+    }
+    tls
+}
+
+#[test]
+fn test_parse() {
+    let s = r#"{
+        "outbounds": [
+            { "type": "selector", "tag": "proxy", "outbounds": ["hk"] },
+            {
+                "type": "vless", "tag": "hk", "server": "1.2.3.4", "server_port": 443,
+                "uuid": "b831381d-6324-4d53-ad4f-8cda48b30811", "flow": "xtls-rprx-vision",
+                "tls": {
+                    "enabled": true, "server_name": "www.microsoft.com",
+                    "utls": { "enabled": true, "fingerprint": "chrome" },
+                    "reality": { "enabled": true, "public_key": "pbk", "short_id": "6ba85179" }
+                }
+            },
+            {
+                "type": "hysteria2", "tag": "hy2", "server": "hy.example.com", "server_port": 8443,
+                "password": "letmein", "obfs": { "type": "salamander", "password": "gawr" },
+                "tls": { "enabled": true, "server_name": "hy.example.com", "alpn": ["h3"] }
+            },
+            {
+                "type": "shadowsocks", "tag": "ss", "server": "1.2.3.4", "server_port": 8388,
+                "method": "2022-blake3-aes-128-gcm", "password": "key"
+            },
+            { "type": "direct", "tag": "direct" }
+        ]
+    }"#;
+    let nodes = parse(s).unwrap();
+    assert_eq!(nodes.len(), 3);
+    assert_eq!(
+        nodes[0].to_string(),
+        "vless://b831381d-6324-4d53-ad4f-8cda48b30811@1.2.3.4:443?flow=xtls-rprx-vision&security=reality&pbk=pbk&sid=6ba85179&sni=www.microsoft.com&fp=chrome#hk"
+    );
+    assert_eq!(
+        nodes[1].to_string(),
+        "hysteria2://letmein@hy.example.com:8443?obfs=salamander&obfs-password=gawr&sni=hy.example.com&alpn=h3#hy2"
+    );
+    assert_eq!(nodes[2].name().unwrap(), "ss");
+}
+
+#[test]
+fn test_render() {
+    use super::{config::subscription, region::Regions};
+
+    let links = [
+        "vless://b831381d-6324-4d53-ad4f-8cda48b30811@1.2.3.4:443?flow=xtls-rprx-vision&security=reality&sni=www.microsoft.com&fp=chrome&pbk=pbk&sid=6ba85179#HK",
+        "ss://YWVzLTEyOC1nY206dGVzdA@192.168.100.1:8888#ss",
+    ];
+    let mut airport =
+        Airport::new("A", links.join("\n"), super::config::Format::Base64, false).unwrap();
+    airport
+        .rename(&subscription(""), &Regions::default())
+        .unwrap();
+    let options: SingBoxOutput = toml::from_str(r#"template = "template.json""#).unwrap();
+    let template = r#"{ "route": { "final": "proxy" }, "outbounds": [{ "type": "direct", "tag": "direct" }] }"#;
+    let rendered = render(&[airport], template, &options, &mut vec![]).unwrap();
+    let document: Value = serde_json::from_str(&rendered).unwrap();
+
+    let tags: Vec<&str> = document["outbounds"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|o| o["tag"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        tags,
+        vec![
+            PROXY,
+            AUTO,
+            "A",
+            "A - auto",
+            "🇭🇰 HK",
+            "HK - A",
+            "ss - A",
+            "direct"
+        ]
+    );
+    let vless = &document["outbounds"][5];
+    assert_eq!(vless["tls"]["reality"]["public_key"], "pbk");
+    assert_eq!(vless["tls"]["utls"]["fingerprint"], "chrome");
+    assert_eq!(document["route"]["final"], "proxy");
+
+    // 解析回来应当一致
+    let nodes = parse(&rendered).unwrap();
+    assert_eq!(nodes.len(), 2);
+}
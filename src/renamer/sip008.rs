@@ -0,0 +1,77 @@
+//! Shadowsocks SIP008 在线配置的解析
+use super::parse::Node;
+use anyhow::Result;
+
+#[derive(Debug, Deserialize)]
+struct Document {
+    servers: Vec<Server>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Server {
+    #[serde(default)]
+    remarks: String,
+    server: String,
+    server_port: u16,
+    password: String,
+    method: String,
+    #[serde(default)]
+    plugin: Option<String>,
+    #[serde(default)]
+    plugin_opts: Option<String>,
+}
+
+pub fn parse(content: &str) -> Result<Vec<Node>> {
+    let document: Document = serde_json::from_str(content)?;
+    let nodes = document
+        .servers
+        .into_iter()
+        .map(|server| {
+            let plugin = match (server.plugin, server.plugin_opts) {
+                (Some(plugin), Some(opts)) if !plugin.is_empty() && !opts.is_empty() => {
+                    Some(format!("{};{}", plugin, opts))
+                }
+                (Some(plugin), _) if !plugin.is_empty() => Some(plugin),
+                _ => None,
+            };
+            Node::Shadowsocks {
+                method: server.method,
+                password: server.password,
+                server: server.server,
+                port: server.server_port,
+                plugin,
+                query: vec![],
+                tag: server.remarks,
+            }
+        })
+        .collect();
+    Ok(nodes)
+}
+
+#[test]
+fn test_parse() {
+    let s = r#"{
+        "version": 1,
+        "servers": [
+            {
+                "id": "27b8a625-4f4b-4428-9f0f-8a2317db7c79",
+                "remarks": "Name of the server",
+                "server": "example.com",
+                "server_port": 8388,
+                "password": "example",
+                "method": "chacha20-ietf-poly1305",
+                "plugin": "xxx",
+                "plugin_opts": "xxxxx"
+            }
+        ],
+        "bytes_used": 274877906944,
+        "bytes_remaining": 824633720832
+    }"#;
+    let nodes = parse(s).unwrap();
+    assert_eq!(nodes.len(), 1);
+    assert_eq!(nodes[0].name().unwrap(), "Name of the server");
+    assert_eq!(
+        nodes[0].to_string(),
+        "ss://Y2hhY2hhMjAtaWV0Zi1wb2x5MTMwNTpleGFtcGxl@example.com:8388/?plugin=xxx%3Bxxxxx#Name%20of%20the%20server"
+    );
+}
@@ -0,0 +1,414 @@
+//! Surge / Quantumult X / Loon 的节点行。
+//!
+//! 节点先转换为 clash 的 proxy，再从中取字段，这样各协议的参数只需要解析一次。
+use super::{
+    clash::{self, Proxy},
+    output,
+    parse::{Airport, Node},
+    view::View,
+};
+use anyhow::{bail, Result};
+use serde_yaml::Value;
+
+/// 逐个节点生成一行，无法转换的节点记录在 `skipped` 中
+pub fn render(
+    airports: &[Airport],
+    line: fn(&Node) -> Result<String>,
+    skipped: &mut Vec<String>,
+) -> String {
+    let mut lines = vec![];
+    for node in airports.iter().flat_map(|airport| airport.nodes.iter()) {
+        match line(node) {
+            Ok(line) => lines.push(line),
+            Err(e) => output::skip(skipped, node, &e),
+        }
+    }
+    lines.join("\n")
+}
+
+fn proxy(node: &Node) -> Result<Value> {
+    Ok(Value::Mapping(clash::to_proxy(node)?))
+}
+
+/// 名字中不能出现分隔符
+fn check_name<'a>(p: &Proxy<'a>, separators: &[char]) -> Result<&'a str> {
+    let name = p.require("name")?;
+    if let Some(c) = name.chars().find(|c| separators.contains(c)) {
+        bail!("节点名中包含 {:?}", c);
+    }
+    Ok(name)
+}
+
+/// 只支持 tcp 和 ws 传输，返回 ws 的 (path, host)
+fn websocket<'a>(p: &Proxy<'a>, client: &str) -> Result<Option<(&'a str, Option<&'a str>)>> {
+    match p.str("network") {
+        None | Some("tcp") => Ok(None),
+        Some("ws") => {
+            let opts = p.opts("ws-opts");
+            Ok(Some((
+                opts.str("path").unwrap_or("/"),
+                opts.opts("headers").str("Host"),
+            )))
+        }
+        Some(other) => bail!("{} 不支持 {} 传输", client, other),
+    }
+}
+
+fn alter_id(p: &Proxy) -> u64 {
+    p.get("alterId").and_then(Value::as_u64).unwrap_or(0)
+}
+
+/// `Name = vmess, server, port, ...`
+pub fn surge(node: &Node) -> Result<String> {
+    let value = proxy(node)?;
+    let p = View(&value);
+    let name = check_name(&p, &[',', '='])?;
+    let kind = p.require("type")?;
+    let mut parts = vec![
+        kind.to_string(),
+        p.require("server")?.to_string(),
+        p.port("port")?.to_string(),
+    ];
+    match kind {
+        "ss" => {
+            parts.push(format!("encrypt-method={}", p.require("cipher")?));
+            parts.push(format!("password={}", p.require("password")?));
+            match p.str("plugin") {
+                Some("obfs") => {
+                    let opts = p.opts("plugin-opts");
+                    parts.push(format!("obfs={}", opts.str("mode").unwrap_or("http")));
+                    if let Some(host) = opts.str("host") {
+                        parts.push(format!("obfs-host={}", host));
+                    }
+                }
+                Some(other) => bail!("surge 不支持 {} 插件", other),
+                None => {}
+            }
+            parts.push("udp-relay=true".to_string());
+        }
+        "vmess" => {
+            parts.push(format!("username={}", p.require("uuid")?));
+            if alter_id(&p) == 0 {
+                parts.push("vmess-aead=true".to_string());
+            }
+        }
+        "trojan" => parts.push(format!("password={}", p.require("password")?)),
+        "hysteria2" => {
+            if p.str("obfs").is_some() {
+                bail!("surge 不支持 hysteria2 混淆");
+            }
+            parts.push(format!(
+                "password={}",
+                p.str("password").unwrap_or_default()
+            ));
+        }
+        "tuic" => {
+            parts[0] = "tuic-v5".to_string();
+            parts.push(format!(
+                "password={}",
+                p.str("password").unwrap_or_default()
+            ));
+            parts.push(format!("uuid={}", p.require("uuid")?));
+        }
+        other => bail!("surge 不支持 {} 协议", other),
+    }
+
+    if p.bool("tls") {
+        parts.push("tls=true".to_string());
+    }
+    if let Some(sni) = p.str("servername").or_else(|| p.str("sni")) {
+        parts.push(format!("sni={}", sni));
+    }
+    if let Some(alpn) = p.get("alpn").and_then(Value::as_sequence) {
+        if let Some(alpn) = alpn.first().and_then(Value::as_str) {
+            parts.push(format!("alpn={}", alpn));
+        }
+    }
+    if p.bool("skip-cert-verify") {
+        parts.push("skip-cert-verify=true".to_string());
+    }
+    if let Some((path, host)) = websocket(&p, "surge")? {
+        parts.push("ws=true".to_string());
+        parts.push(format!("ws-path={}", path));
+        if let Some(host) = host {
+            parts.push(format!("ws-headers=Host:{}", host));
+        }
+    }
+
+    Ok(format!("{} = {}", name, parts.join(", ")))
+}
+
+/// `[server_local]` 中的一行，`vmess=server:port, ..., tag=Name`
+pub fn quantumult_x(node: &Node) -> Result<String> {
+    let value = proxy(node)?;
+    let p = View(&value);
+    let name = check_name(&p, &[','])?;
+    let kind = p.require("type")?;
+    let address = format!("{}:{}", p.require("server")?, p.port("port")?);
+    let mut parts = vec![];
+    let tls = p.bool("tls");
+    let sni = p.str("servername").or_else(|| p.str("sni"));
+
+    // ws / wss / over-tls 共用的 obfs 参数
+    let obfs = |parts: &mut Vec<String>, tls: bool| -> Result<()> {
+        match websocket(&p, "quantumult x")? {
+            Some((path, host)) => {
+                parts.push(format!("obfs={}", if tls { "wss" } else { "ws" }));
+                if let Some(host) = host.or(sni) {
+                    parts.push(format!("obfs-host={}", host));
+                }
+                parts.push(format!("obfs-uri={}", path));
+            }
+            None if tls => {
+                parts.push("obfs=over-tls".to_string());
+                if let Some(host) = sni {
+                    parts.push(format!("obfs-host={}", host));
+                }
+            }
+            None => {}
+        }
+        Ok(())
+    };
+
+    match kind {
+        "ss" => {
+            parts.push(format!("shadowsocks={}", address));
+            parts.push(format!("method={}", p.require("cipher")?));
+            parts.push(format!("password={}", p.require("password")?));
+            let opts = p.opts("plugin-opts");
+            match p.str("plugin") {
+                Some("obfs") => {
+                    parts.push(format!("obfs={}", opts.str("mode").unwrap_or("http")));
+                    if let Some(host) = opts.str("host") {
+                        parts.push(format!("obfs-host={}", host));
+                    }
+                }
+                Some("v2ray-plugin") => {
+                    let tls = opts.bool("tls");
+                    parts.push(format!("obfs={}", if tls { "wss" } else { "ws" }));
+                    if let Some(host) = opts.str("host") {
+                        parts.push(format!("obfs-host={}", host));
+                    }
+                    parts.push(format!("obfs-uri={}", opts.str("path").unwrap_or("/")));
+                }
+                Some(other) => bail!("quantumult x 不支持 {} 插件", other),
+                None => {}
+            }
+            parts.push("udp-relay=true".to_string());
+        }
+        "ssr" => {
+            parts.push(format!("shadowsocks={}", address));
+            parts.push(format!("method={}", p.require("cipher")?));
+            parts.push(format!("password={}", p.require("password")?));
+            parts.push(format!("ssr-protocol={}", p.require("protocol")?));
+            if let Some(param) = p.str("protocol-param") {
+                parts.push(format!("ssr-protocol-param={}", param));
+            }
+            parts.push(format!("obfs={}", p.require("obfs")?));
+            if let Some(param) = p.str("obfs-param") {
+                parts.push(format!("obfs-host={}", param));
+            }
+        }
+        "vmess" => {
+            let method = match p.str("cipher") {
+                Some("none") => "none",
+                Some("aes-128-gcm") => "aes-128-gcm",
+                _ => "chacha20-poly1305",
+            };
+            parts.push(format!("vmess={}", address));
+            parts.push(format!("method={}", method));
+            parts.push(format!("password={}", p.require("uuid")?));
+            obfs(&mut parts, tls)?;
+            if alter_id(&p) != 0 {
+                parts.push("aead=false".to_string());
+            }
+        }
+        "trojan" => {
+            parts.push(format!("trojan={}", address));
+            parts.push(format!("password={}", p.require("password")?));
+            if websocket(&p, "quantumult x")?.is_some() {
+                obfs(&mut parts, true)?;
+            } else {
+                parts.push("over-tls=true".to_string());
+                if let Some(sni) = sni {
+                    parts.push(format!("tls-host={}", sni));
+                }
+            }
+        }
+        "vless" => {
+            if p.get("reality-opts").is_some() {
+                bail!("quantumult x 不支持 reality");
+            }
+            if p.str("flow").is_some() {
+                bail!("quantumult x 不支持 flow");
+            }
+            parts.push(format!("vless={}", address));
+            parts.push("method=none".to_string());
+            parts.push(format!("password={}", p.require("uuid")?));
+            obfs(&mut parts, tls)?;
+        }
+        other => bail!("quantumult x 不支持 {} 协议", other),
+    }
+
+    if p.bool("skip-cert-verify") {
+        parts.push("tls-verification=false".to_string());
+    }
+    parts.push(format!("tag={}", name));
+    Ok(parts.join(", "))
+}
+
+/// `Name = vmess,server,port,...`
+pub fn loon(node: &Node) -> Result<String> {
+    let value = proxy(node)?;
+    let p = View(&value);
+    let name = check_name(&p, &[',', '='])?;
+    let kind = p.require("type")?;
+    let server = p.require("server")?.to_string();
+    let port = p.port("port")?.to_string();
+    let quote = |s: &str| format!("\"{}\"", s);
+
+    let mut parts = match kind {
+        "ss" => {
+            let mut parts = vec![
+                "Shadowsocks".to_string(),
+                server,
+                port,
+                p.require("cipher")?.to_string(),
+                quote(p.require("password")?),
+            ];
+            match p.str("plugin") {
+                Some("obfs") => {
+                    let opts = p.opts("plugin-opts");
+                    parts.push(format!("obfs-name={}", opts.str("mode").unwrap_or("http")));
+                    if let Some(host) = opts.str("host") {
+                        parts.push(format!("obfs-host={}", host));
+                    }
+                }
+                Some(other) => bail!("loon 不支持 {} 插件", other),
+                None => {}
+            }
+            parts
+        }
+        "ssr" => vec![
+            "ShadowsocksR".to_string(),
+            server,
+            port,
+            p.require("cipher")?.to_string(),
+            quote(p.require("password")?),
+            format!("protocol={}", p.require("protocol")?),
+            format!(
+                "protocol-param={}",
+                p.str("protocol-param").unwrap_or_default()
+            ),
+            format!("obfs={}", p.require("obfs")?),
+            format!("obfs-param={}", p.str("obfs-param").unwrap_or_default()),
+        ],
+        "vmess" => vec![
+            "vmess".to_string(),
+            server,
+            port,
+            p.str("cipher").unwrap_or("auto").to_string(),
+            quote(p.require("uuid")?),
+            format!("alterId={}", alter_id(&p)),
+        ],
+        "trojan" => vec![
+            "trojan".to_string(),
+            server,
+            port,
+            quote(p.require("password")?),
+        ],
+        "vless" => {
+            if p.get("reality-opts").is_some() {
+                bail!("loon 不支持 reality");
+            }
+            let mut parts = vec!["VLESS".to_string(), server, port, quote(p.require("uuid")?)];
+            if let Some(flow) = p.str("flow") {
+                parts.push(format!("flow={}", flow));
+            }
+            parts
+        }
+        "hysteria2" => {
+            if p.str("obfs").is_some() {
+                bail!("loon 不支持 hysteria2 混淆");
+            }
+            vec![
+                "Hysteria2".to_string(),
+                server,
+                port,
+                quote(p.str("password").unwrap_or_default()),
+            ]
+        }
+        other => bail!("loon 不支持 {} 协议", other),
+    };
+
+    if matches!(kind, "vmess" | "trojan" | "vless") {
+        match websocket(&p, "loon")? {
+            Some((path, host)) => {
+                parts.push("transport=ws".to_string());
+                parts.push(format!("path={}", path));
+                if let Some(host) = host {
+                    parts.push(format!("host={}", host));
+                }
+            }
+            None => parts.push("transport=tcp".to_string()),
+        }
+    }
+    if p.bool("tls") {
+        parts.push("over-tls=true".to_string());
+    }
+    if let Some(sni) = p.str("servername").or_else(|| p.str("sni")) {
+        parts.push(format!("tls-name={}", sni));
+    }
+    if p.bool("skip-cert-verify") {
+        parts.push("skip-cert-verify=true".to_string());
+    }
+
+    Ok(format!("{} = {}", name, parts.join(",")))
+}
+
+#[test]
+fn test_export() {
+    let vmess = serde_json::json!({
+        "v": "2", "ps": "香港 01", "add": "hk.example.com", "port": "443",
+        "id": "b831381d-6324-4d53-ad4f-8cda48b30811", "aid": "0", "scy": "auto",
+        "net": "ws", "type": "none", "host": "cdn.example.com", "path": "/ray",
+        "tls": "tls", "sni": "hk.example.com",
+    });
+    let vmess = format!("vmess://{}", base64::encode(vmess.to_string()));
+    let node: Node = vmess.parse().unwrap();
+    assert_eq!(
+        surge(&node).unwrap(),
+        "香港 01 = vmess, hk.example.com, 443, username=b831381d-6324-4d53-ad4f-8cda48b30811, vmess-aead=true, tls=true, sni=hk.example.com, ws=true, ws-path=/ray, ws-headers=Host:cdn.example.com"
+    );
+    assert_eq!(
+        quantumult_x(&node).unwrap(),
+        "vmess=hk.example.com:443, method=chacha20-poly1305, password=b831381d-6324-4d53-ad4f-8cda48b30811, obfs=wss, obfs-host=cdn.example.com, obfs-uri=/ray, tag=香港 01"
+    );
+    assert_eq!(
+        loon(&node).unwrap(),
+        "香港 01 = vmess,hk.example.com,443,auto,\"b831381d-6324-4d53-ad4f-8cda48b30811\",alterId=0,transport=ws,path=/ray,host=cdn.example.com,over-tls=true,tls-name=hk.example.com"
+    );
+
+    let ss: Node = "ss://YWVzLTEyOC1nY206dGVzdA@1.2.3.4:8888/?plugin=obfs-local%3Bobfs%3Dhttp%3Bobfs-host%3Dbing.com#ss"
+        .parse()
+        .unwrap();
+    assert_eq!(
+        surge(&ss).unwrap(),
+        "ss = ss, 1.2.3.4, 8888, encrypt-method=aes-128-gcm, password=test, obfs=http, obfs-host=bing.com, udp-relay=true"
+    );
+
+    // reality 在 quantumult x 中没有对应
+    let reality = "vless://uuid@1.2.3.4:443?security=reality&pbk=pbk&sid=1#reality";
+    let vless: Node = reality.parse().unwrap();
+    assert!(quantumult_x(&vless).is_err());
+    assert!(surge(&vless).is_err());
+
+    let links = [vmess.as_str(), reality];
+    let airport =
+        Airport::new("A", links.join("\n"), super::config::Format::Base64, false).unwrap();
+    let mut skipped = vec![];
+    let rendered = render(&[airport], surge, &mut skipped);
+    assert_eq!(rendered.lines().count(), 1);
+    assert_eq!(skipped.len(), 1);
+    assert!(skipped[0].starts_with("reality："));
+}
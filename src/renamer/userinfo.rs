@@ -0,0 +1,157 @@
+//! 机场在 `subscription-userinfo` 响应头中返回的流量和到期信息
+use anyhow::{Context, Result};
+use chrono::{DateTime, TimeZone, Utc};
+use std::{fmt, str::FromStr};
+
+/// 响应头名
+pub const HEADER: &str = "subscription-userinfo";
+
+/// `upload=1; download=2; total=3; expire=1700000000`，流量的单位是字节
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UserInfo {
+    pub upload: u64,
+    pub download: u64,
+    pub total: u64,
+    /// 到期时间戳，单位秒；没有或为 0 时表示不过期
+    pub expire: Option<i64>,
+}
+impl UserInfo {
+    pub fn used(&self) -> u64 {
+        self.upload + self.download
+    }
+
+    pub fn remaining(&self) -> u64 {
+        self.total.saturating_sub(self.used())
+    }
+
+    /// 已用流量的比例，总流量未知时为 `None`
+    pub fn usage(&self) -> Option<f64> {
+        if self.total == 0 {
+            return None;
+        }
+        Some(self.used() as f64 / self.total as f64)
+    }
+
+    pub fn expire_at(&self) -> Option<DateTime<Utc>> {
+        self.expire.and_then(|t| Utc.timestamp_opt(t, 0).single())
+    }
+}
+
+/// 多个机场的流量相加，到期时间取最早的
+pub fn aggregate<'a>(infos: impl IntoIterator<Item = &'a UserInfo>) -> Option<UserInfo> {
+    infos.into_iter().fold(None, |acc, info| {
+        let acc = match acc {
+            Some(acc) => acc,
+            None => return Some(*info),
+        };
+        let expire = match (acc.expire, info.expire) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+        Some(UserInfo {
+            upload: acc.upload + info.upload,
+            download: acc.download + info.download,
+            total: acc.total + info.total,
+            expire,
+        })
+    })
+}
+
+impl FromStr for UserInfo {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut info = Self::default();
+        for pair in s.split(';').map(str::trim).filter(|p| !p.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| anyhow!("= not found in {:?}", pair))?;
+            let value = value.trim();
+            // 有的机场会返回 `total=1.5e+12` 这样的浮点数
+            let number = || -> Result<u64> {
+                value
+                    .parse::<u64>()
+                    .or_else(|_| value.parse::<f64>().map(|v| v as u64))
+                    .with_context(|| format!("invalid {} = {:?}", key, value))
+            };
+            match key.trim() {
+                "upload" => info.upload = number()?,
+                "download" => info.download = number()?,
+                "total" => info.total = number()?,
+                "expire" if value.is_empty() => {}
+                "expire" => info.expire = Some(number()? as i64).filter(|t| *t > 0),
+                key => debug!("unknown userinfo key {:?}", key),
+            }
+        }
+        Ok(info)
+    }
+}
+
+/// 按响应头的格式输出
+impl fmt::Display for UserInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "upload={}; download={}; total={}",
+            self.upload, self.download, self.total
+        )?;
+        if let Some(expire) = self.expire {
+            write!(f, "; expire={}", expire)?;
+        }
+        Ok(())
+    }
+}
+
+/// 人类可读的流量，如 `1.50 GB`
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KB", "MB", "GB", "TB", "PB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.2} {}", value, UNITS[unit])
+    }
+}
+
+#[test]
+fn test_parse() {
+    let info: UserInfo =
+        "upload=1073741824; download=2147483648; total=10737418240; expire=1700000000"
+            .parse()
+            .unwrap();
+    assert_eq!(info.used(), 3 << 30);
+    assert_eq!(info.remaining(), 7 << 30);
+    assert_eq!(info.usage(), Some(0.3));
+    assert_eq!(
+        info.expire_at().unwrap().to_rfc3339(),
+        "2023-11-14T22:13:20+00:00"
+    );
+    assert_eq!(info.to_string().parse::<UserInfo>().unwrap(), info);
+    assert_eq!(format_bytes(info.remaining()), "7.00 GB");
+
+    let info: UserInfo = "upload=0;download=1.5e3;total=0;expire=".parse().unwrap();
+    assert_eq!(info.download, 1500);
+    assert_eq!(info.usage(), None);
+    assert_eq!(info.expire, None);
+    assert!("upload".parse::<UserInfo>().is_err());
+}
+
+#[test]
+fn test_aggregate() {
+    let a: UserInfo = "upload=1; download=2; total=10; expire=200"
+        .parse()
+        .unwrap();
+    let b: UserInfo = "upload=3; download=4; total=20".parse().unwrap();
+    let c: UserInfo = "upload=0; download=0; total=5; expire=100".parse().unwrap();
+    assert_eq!(aggregate(&[]), None);
+    assert_eq!(aggregate(&[a]), Some(a));
+    assert_eq!(
+        aggregate(&[a, b, c]).unwrap().to_string(),
+        "upload=4; download=6; total=35; expire=100"
+    );
+}
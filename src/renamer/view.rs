@@ -0,0 +1,121 @@
+//! clash（yaml）和 sing-box（json）配置中单个节点的只读视图
+use anyhow::{anyhow, Result};
+use std::convert::TryFrom;
+
+/// yaml 和 json 的值共有的操作
+pub(super) trait Tree: 'static {
+    /// 出错时提示的来源
+    const SOURCE: &'static str;
+
+    fn null() -> &'static Self;
+    fn field(&self, key: &str) -> Option<&Self>;
+    fn as_str(&self) -> Option<&str>;
+    fn as_bool(&self) -> Option<bool>;
+    fn as_u64(&self) -> Option<u64>;
+}
+
+impl Tree for serde_yaml::Value {
+    const SOURCE: &'static str = "clash";
+
+    fn null() -> &'static Self {
+        static NULL: serde_yaml::Value = serde_yaml::Value::Null;
+        &NULL
+    }
+
+    fn field(&self, key: &str) -> Option<&Self> {
+        self.get(key)
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        self.as_str()
+    }
+
+    fn as_bool(&self) -> Option<bool> {
+        self.as_bool()
+    }
+
+    fn as_u64(&self) -> Option<u64> {
+        self.as_u64()
+    }
+}
+
+impl Tree for serde_json::Value {
+    const SOURCE: &'static str = "sing-box";
+
+    fn null() -> &'static Self {
+        static NULL: serde_json::Value = serde_json::Value::Null;
+        &NULL
+    }
+
+    fn field(&self, key: &str) -> Option<&Self> {
+        self.get(key)
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        self.as_str()
+    }
+
+    fn as_bool(&self) -> Option<bool> {
+        self.as_bool()
+    }
+
+    fn as_u64(&self) -> Option<u64> {
+        self.as_u64()
+    }
+}
+
+pub(super) struct View<'a, V>(pub &'a V);
+impl<'a, V: Tree> View<'a, V> {
+    pub fn get(&self, key: &str) -> Option<&'a V> {
+        self.0.field(key)
+    }
+
+    pub fn str(&self, key: &str) -> Option<&'a str> {
+        self.get(key).and_then(V::as_str)
+    }
+
+    pub fn require(&self, key: &str) -> Result<&'a str> {
+        self.str(key)
+            .ok_or_else(|| anyhow!("{}: field {} not found", V::SOURCE, key))
+    }
+
+    pub fn bool(&self, key: &str) -> bool {
+        self.get(key).and_then(V::as_bool).unwrap_or(false)
+    }
+
+    /// 端口可能是数字也可能是字符串，超出范围时报错
+    pub fn port(&self, key: &str) -> Result<u16> {
+        let value = self
+            .get(key)
+            .ok_or_else(|| anyhow!("{}: field {} not found", V::SOURCE, key))?;
+        let port = match (value.as_u64(), value.as_str()) {
+            (Some(n), _) => u16::try_from(n).ok(),
+            (None, Some(s)) => s.parse().ok(),
+            (None, None) => None,
+        };
+        port.ok_or_else(|| anyhow!("{}: invalid {}", V::SOURCE, key))
+    }
+
+    /// `ws-opts` / `tls` 之类的子字段
+    pub fn opts(&self, key: &str) -> View<'a, V> {
+        View(self.get(key).unwrap_or_else(|| V::null()))
+    }
+}
+
+#[test]
+fn test_port() {
+    let yaml: serde_yaml::Value =
+        serde_yaml::from_str("{ a: 443, b: '8443', c: 70000, d: x }").unwrap();
+    let p = View(&yaml);
+    assert_eq!(p.port("a").unwrap(), 443);
+    assert_eq!(p.port("b").unwrap(), 8443);
+    assert!(p.port("c").is_err());
+    assert!(p.port("d").is_err());
+    assert!(p.port("e").is_err());
+
+    let json = serde_json::json!({ "server_port": 70000, "tls": { "enabled": true } });
+    let o = View(&json);
+    assert!(o.port("server_port").is_err());
+    assert!(o.opts("tls").bool("enabled"));
+    assert!(!o.opts("missing").bool("enabled"));
+}